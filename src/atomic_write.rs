@@ -0,0 +1,94 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn temp_path_for(path: &Path) -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	path.with_file_name(format!(".{file_name}.svgc-tmp-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Writes a file so that `path` contains either its previous content or the complete new one.
+///
+/// The content is written by `write` to a sibling temporary file, which is synced to disk and then
+/// renamed over `path`. The new file has the default permissions, the attributes of the previous
+/// one are carried over by the callers with `Preserve`. As `path` is replaced by another file,
+/// its hard links keep the previous content, and its extended attributes and ACLs are not kept.
+pub fn write_atomically_with(path: &Path, write: impl FnOnce(&mut io::BufWriter<&fs::File>) -> io::Result<()>) -> io::Result<()> {
+	let temp_path = temp_path_for(path);
+	let result = (|| {
+		let file = fs::File::create_new(&temp_path)?;
+		let mut writer = io::BufWriter::new(&file);
+		write(&mut writer)?;
+		writer.flush()?;
+		drop(writer);
+		file.sync_all()?;
+		fs::rename(&temp_path, path)
+	})();
+	if result.is_err() {
+		let _ = fs::remove_file(&temp_path);
+		return result
+	}
+	sync_parent_dir(path);
+	Ok(())
+}
+
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+	write_atomically_with(path, |writer| writer.write_all(contents.as_ref()))
+}
+
+/// Makes the rename durable. Best-effort, as not every platform allows syncing directories.
+fn sync_parent_dir(path: &Path) {
+	#[cfg(unix)]
+	if let Some(dir) = path.parent() {
+		let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+		if let Ok(dir) = fs::File::open(dir) {
+			let _ = dir.sync_all();
+		}
+	}
+	#[cfg(not(unix))]
+	let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_write_atomically() {
+		let dir = std::env::temp_dir().join(format!("svgc_test_atomic_write_{}", process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("icon.svg");
+
+		write_atomically(&path, "<svg/>").unwrap();
+		assert_eq!(fs::read_to_string(&path).unwrap(), "<svg/>");
+
+		let result = write_atomically_with(&path, |writer| {
+			writer.write_all(b"<sv")?;
+			Err(io::Error::other("interrupted"))
+		});
+		assert!(result.is_err());
+		assert_eq!(fs::read_to_string(&path).unwrap(), "<svg/>");
+		assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
\ No newline at end of file
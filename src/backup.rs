@@ -0,0 +1,144 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic_write::{write_atomically, write_atomically_with};
+use crate::preserve::Preserve;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Description of a persistent backup run, stored next to the backed up files.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+	pub version: String,
+	pub created: String,
+	pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+	pub original_path: PathBuf,
+	/// Name of the backup file inside the backup run directory.
+	pub backup_file: PathBuf,
+	/// Path of the file produced by the run, if it differs from the original one.
+	pub result_path: Option<PathBuf>,
+	/// Precompressed copies of the result, such as `icon.svg.gz`.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub precompressed_paths: Vec<PathBuf>,
+	pub sha256: String,
+}
+
+impl Manifest {
+	pub fn read(backup_dir: &Path) -> io::Result<Self> {
+		let content = fs::read(backup_dir.join(MANIFEST_FILE_NAME))?;
+		serde_json::from_slice(&content).map_err(io::Error::from)
+	}
+
+	pub fn write(&self, backup_dir: &Path) -> io::Result<()> {
+		write_atomically(&backup_dir.join(MANIFEST_FILE_NAME), serde_json::to_vec_pretty(self)?)
+	}
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+	let mut hasher = Sha256::new();
+	io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+	Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Puts the files of a persistent backup run back to their original locations.
+///
+/// Every backup is checked against the hash from the manifest before anything is touched.
+/// Returns the restored paths.
+pub fn restore_backup_run(backup_dir: &Path) -> io::Result<Vec<PathBuf>> {
+	let manifest = Manifest::read(backup_dir)?;
+
+	for entry in &manifest.files {
+		let backup_path = backup_dir.join(&entry.backup_file);
+		if sha256_file(&backup_path)? != entry.sha256 {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, t!("backup-hash-mismatch", path = backup_path.display())))
+		}
+	}
+
+	let mut restored = Vec::with_capacity(manifest.files.len());
+	for entry in manifest.files {
+		let mut backup = fs::File::open(backup_dir.join(&entry.backup_file))?;
+		write_atomically_with(&entry.original_path, |writer| io::copy(&mut backup, writer).map(|_| ()))?;
+		// The backup is a copy of the original file with its permissions, but not its timestamps
+		Preserve { mode: true, ..Preserve::default() }.apply(&backup.metadata()?, &entry.original_path)?;
+		if let Some(result_path) = entry.result_path {
+			if result_path != entry.original_path && result_path.try_exists()? {
+				fs::remove_file(result_path)?;
+			}
+		}
+		for path in entry.precompressed_paths {
+			if path.try_exists()? {
+				fs::remove_file(path)?;
+			}
+		}
+		restored.push(entry.original_path);
+	}
+	Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_restore_backup_run() {
+		let dir = std::env::temp_dir().join(format!("svgc_test_restore_backup_run_{}", std::process::id()));
+		let backup_dir = dir.join("backup");
+		fs::create_dir_all(&backup_dir).unwrap();
+		let original_path = dir.join("icon.svg");
+		let result_path = dir.join("icon.svgz");
+		fs::write(&original_path, "<svg> </svg>").unwrap();
+		fs::copy(&original_path, backup_dir.join("icon.svg")).unwrap();
+		let manifest = Manifest {
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			created: String::new(),
+			files: vec![ManifestEntry {
+				original_path: original_path.clone(),
+				backup_file: PathBuf::from("icon.svg"),
+				result_path: Some(result_path.clone()),
+				precompressed_paths: Vec::new(),
+				sha256: sha256_file(&original_path).unwrap(),
+			}],
+		};
+		manifest.write(&backup_dir).unwrap();
+
+		// A tampered backup fails the restore before any file is touched
+		fs::write(&original_path, "<svg/>").unwrap();
+		fs::write(&result_path, "svgz").unwrap();
+		fs::write(backup_dir.join("icon.svg"), "<svg>tampered</svg>").unwrap();
+		assert!(restore_backup_run(&backup_dir).is_err());
+		assert_eq!(fs::read_to_string(&original_path).unwrap(), "<svg/>");
+		assert!(result_path.exists());
+
+		fs::write(backup_dir.join("icon.svg"), "<svg> </svg>").unwrap();
+		assert_eq!(restore_backup_run(&backup_dir).unwrap(), vec![original_path.clone()]);
+		assert_eq!(fs::read_to_string(&original_path).unwrap(), "<svg> </svg>");
+		assert!(!result_path.exists());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
\ No newline at end of file
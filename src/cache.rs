@@ -0,0 +1,158 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::atomic_write::write_atomically;
+use crate::backup::sha256_file;
+
+pub const DEFAULT_CACHE_FILE_NAME: &str = ".svgc-cache";
+
+/// Hashes of the files produced by previous runs.
+///
+/// Each line of the cache file holds a key of the program version and options, and a hash of a
+/// file produced with them. A file whose content has such a hash is already optimized.
+pub struct Cache {
+	/// `None` for a cache that is not saved.
+	path: Option<PathBuf>,
+	options_key: String,
+	entries: BTreeSet<String>,
+}
+
+impl Cache {
+	/// `options` must describe every option that affects the produced files.
+	pub fn load(path: &Path, options: &str) -> io::Result<Self> {
+		let entries = match fs::read_to_string(path) {
+			Ok(content) => content.lines().map(str::to_string).collect(),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+			Err(e) => return Err(e),
+		};
+		Ok(Self { path: Some(path.to_path_buf()), options_key: options_key_for(options), entries })
+	}
+
+	pub fn in_memory(options: &str) -> Self {
+		Self { path: None, options_key: options_key_for(options), entries: BTreeSet::new() }
+	}
+
+	fn entry(&self, hash: &str) -> String {
+		format!("{} {hash}", self.options_key)
+	}
+
+	pub fn contains(&self, hash: &str) -> bool {
+		self.entries.contains(&self.entry(hash))
+	}
+
+	pub fn insert(&mut self, hash: &str) {
+		self.entries.insert(self.entry(hash));
+	}
+
+	/// Splits the files into the ones to process and the ones already optimized, with their sizes.
+	pub fn partition(&self, paths: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<(PathBuf, u64)>) {
+		let (unchanged, to_process): (Vec<_>, Vec<_>) = paths.into_par_iter()
+			.map(|path| {
+				// Files that cannot be read are processed, so the error is reported as usual
+				let size = sha256_file(&path).ok()
+					.filter(|hash| self.contains(hash))
+					.and_then(|_| Some(path.metadata().ok()?.len()));
+				(path, size)
+			})
+			.partition(|(_, size)| size.is_some());
+		(
+			to_process.into_iter().map(|(path, _)| path).collect(),
+			unchanged.into_iter().map(|(path, size)| (path, size.unwrap())).collect(),
+		)
+	}
+
+	/// Inserts the hashes of the files whose content is the same as when they were hashed.
+	///
+	/// In watch mode files restored after a failure are rewritten with their original content, and
+	/// the events of these writes must not get them processed again.
+	pub fn insert_unchanged(&mut self, hashes: &[(PathBuf, String)]) {
+		for (path, hash) in hashes {
+			if sha256_file(path).is_ok_and(|current| current == *hash) {
+				self.insert(hash);
+			}
+		}
+	}
+
+	pub fn save(&self) -> io::Result<()> {
+		let Some(path) = &self.path else {
+			return Ok(())
+		};
+		let mut content = String::new();
+		for entry in &self.entries {
+			content.push_str(entry);
+			content.push('\n');
+		}
+		write_atomically(path, content)
+	}
+}
+
+/// Hashes of the files that can be read.
+pub fn hash_files(paths: &[PathBuf]) -> Vec<(PathBuf, String)> {
+	paths.par_iter()
+		.filter_map(|path| Some((path.clone(), sha256_file(path).ok()?)))
+		.collect()
+}
+
+fn options_key_for(options: &str) -> String {
+	let hash = format!("{:x}", Sha256::digest(format!("{} {options}", env!("CARGO_PKG_VERSION"))));
+	hash[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_cache_keys_options() {
+		let mut cache = Cache::in_memory("svgz");
+		cache.insert("abc");
+		assert!(cache.contains("abc"));
+		assert!(!cache.contains("def"));
+
+		let other = Cache { options_key: options_key_for("svgo"), ..cache };
+		assert!(!other.contains("abc"));
+	}
+
+	#[test]
+	fn test_insert_unchanged() {
+		let dir = std::env::temp_dir().join(format!("svgc_test_cache_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let restored = dir.join("restored.svg");
+		let optimized = dir.join("optimized.svg");
+		fs::write(&restored, "<svg> </svg>").unwrap();
+		fs::write(&optimized, "<svg>  </svg>").unwrap();
+
+		let hashes = hash_files(&[restored.clone(), optimized.clone()]);
+		fs::write(&restored, "<svg> </svg>").unwrap();
+		fs::write(&optimized, "<svg/>").unwrap();
+
+		let mut cache = Cache::in_memory("");
+		cache.insert_unchanged(&hashes);
+		let (to_process, unchanged) = cache.partition(vec![restored.clone(), optimized.clone()]);
+		assert_eq!(to_process, vec![optimized]);
+		assert_eq!(unchanged, vec![(restored, 12)]);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
\ No newline at end of file
@@ -0,0 +1,36 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rust_i18n::t;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGINT and SIGTERM cancel the processing instead of terminating the program,
+/// so running commands are stopped and the files are restored.
+pub fn install_handler() -> Result<(), ctrlc::Error> {
+	ctrlc::set_handler(|| CANCELLED.store(true, Ordering::SeqCst))
+}
+
+pub fn is_cancelled() -> bool {
+	CANCELLED.load(Ordering::SeqCst)
+}
+
+pub fn cancelled_error() -> io::Error {
+	io::Error::new(io::ErrorKind::Interrupted, t!("operation-cancelled"))
+}
\ No newline at end of file
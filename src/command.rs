@@ -0,0 +1,155 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_i18n::t;
+use wait_timeout::ChildExt;
+
+use crate::cancel;
+
+/// Total length of the arguments of one command, well below the limits of the OS.
+#[cfg(windows)]
+pub const MAX_ARGS_LENGTH: usize = 30_000;
+#[cfg(not(windows))]
+pub const MAX_ARGS_LENGTH: usize = 128 * 1024;
+
+/// How often a running command is checked for being cancelled or timed out.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the command, feeding it `input`, and returns what it printed to stdout.
+///
+/// Fails with what the command printed to stderr if it exits unsuccessfully, and kills it when it
+/// runs longer than `timeout` or the processing is cancelled. `name` is the name of the command in
+/// the error messages.
+pub fn run(command: &mut Command, name: &str, input: Option<Vec<u8>>, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+	let stdin = if input.is_some() { Stdio::piped() } else { Stdio::null() };
+	#[cfg(unix)]
+	{
+		use std::os::unix::process::CommandExt;
+		// A group of its own lets the command be stopped together with the processes it starts
+		command.process_group(0);
+	}
+	let mut child = command.stdin(stdin).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+	// Pipes are served by threads, so a command filling one of them while another is full doesn't block
+	let stdin = child.stdin.take();
+	thread::spawn(move || {
+		if let (Some(mut stdin), Some(input)) = (stdin, input) {
+			let _ = stdin.write_all(&input);
+		}
+	});
+	let stdout = read_in_background(child.stdout.take());
+	let stderr = read_in_background(child.stderr.take());
+
+	let deadline = timeout.map(|timeout| Instant::now() + timeout);
+	let status = loop {
+		if let Some(status) = child.wait_timeout(POLL_INTERVAL)? {
+			break status
+		}
+		let error = if cancel::is_cancelled() {
+			cancel::cancelled_error()
+		} else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+			io::Error::new(io::ErrorKind::TimedOut, t!("error-command-timed-out", command = name, seconds = timeout.unwrap().as_secs_f64()))
+		} else {
+			continue
+		};
+		kill(&mut child)?;
+		return Err(error)
+	};
+	// A command interrupted together with the program may exit before noticing the cancellation
+	if cancel::is_cancelled() {
+		return Err(cancel::cancelled_error())
+	}
+	let stdout = stdout.join().unwrap_or_default();
+	if status.success() {
+		return Ok(stdout)
+	}
+	let stderr = stderr.join().unwrap_or_default();
+	let stderr = String::from_utf8_lossy(&stderr);
+	let stderr = stderr.trim();
+	Err(io::Error::other(if stderr.is_empty() {
+		t!("error-command-failed", command = name, status = status)
+	} else {
+		t!("error-command-failed-with-output", command = name, status = status, output = stderr)
+	}))
+}
+
+/// Kills the command together with the processes it started, as tools run through `npx` do.
+fn kill(child: &mut Child) -> io::Result<()> {
+	#[cfg(unix)]
+	// SAFETY: sending a signal has no memory safety requirements
+	unsafe {
+		libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+	}
+	child.kill()?;
+	child.wait().map(|_| ())
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+	thread::spawn(move || {
+		let mut content = Vec::new();
+		if let Some(mut pipe) = pipe {
+			let _ = pipe.read_to_end(&mut content);
+		}
+		content
+	})
+}
+
+pub fn args_length<'a>(args: impl IntoIterator<Item = &'a OsStr>) -> usize {
+	// Each argument is terminated by a null character
+	args.into_iter().map(|arg| arg.len() + 1).sum()
+}
+
+/// Splits the paths into batches whose total length as arguments does not exceed `max_length`.
+/// A path longer than that gets a batch of its own.
+pub fn batches<'a, 'b>(paths: &'b [&'a Path], max_length: usize) -> Vec<&'b [&'a Path]> {
+	let mut batches = Vec::new();
+	let mut start = 0;
+	let mut length = 0;
+	for (i, path) in paths.iter().enumerate() {
+		let path_length = args_length([path.as_os_str()]);
+		if i > start && length + path_length > max_length {
+			batches.push(&paths[start..i]);
+			start = i;
+			length = 0;
+		}
+		length += path_length;
+	}
+	if start < paths.len() {
+		batches.push(&paths[start..]);
+	}
+	batches
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_batches() {
+		let paths = [Path::new("a.svg"), Path::new("b.svg"), Path::new("long.svg"), Path::new("c.svg")];
+		assert_eq!(batches(&paths, 12), [&paths[..2], &paths[2..3], &paths[3..]]);
+		assert_eq!(batches(&paths, 5), [&paths[..1], &paths[1..2], &paths[2..3], &paths[3..]]);
+		assert_eq!(batches(&paths, 1000), [&paths[..]]);
+		assert!(batches(&[], 1000).is_empty());
+	}
+}
\ No newline at end of file
@@ -20,6 +20,8 @@ use std::path::Path;
 use regex::Regex;
 use lazy_static::lazy_static;
 
+use crate::atomic_write::write_atomically;
+
 pub fn default_optimize(filepath: &Path, remove_fill: bool) -> io::Result<()> {
 	let mut content = fs::read_to_string(filepath)?;
 
@@ -53,5 +55,5 @@ pub fn default_optimize(filepath: &Path, remove_fill: bool) -> io::Result<()> {
 		content = R_XML_SPACE.replace_all(&content, "").to_string();
 	}
 
-	fs::write(filepath, content)
+	write_atomically(filepath, content)
 }
\ No newline at end of file
@@ -0,0 +1,170 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rayon::prelude::*;
+use rust_i18n::t;
+
+use crate::atomic_write::write_atomically;
+use crate::command::{self, args_length, batches, MAX_ARGS_LENGTH};
+
+const IN_PLACEHOLDER: &str = "{in}";
+const OUT_PLACEHOLDER: &str = "{out}";
+const FILE_PLACEHOLDER: &str = "{file}";
+const FILES_PLACEHOLDER: &str = "{files}";
+
+/// How an external command gets the file to optimize.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExecMode {
+	/// Reads `{in}` and writes the result to `{out}`.
+	InOut,
+	/// Optimizes `{file}` in place.
+	InPlace,
+	/// Optimizes all `{files}` in place at once.
+	Batch,
+	/// Reads the file from stdin and writes the result to stdout.
+	Stdio,
+}
+
+/// An external optimizer given by a command template, such as `scour {in} {out}`.
+#[derive(Clone, Debug)]
+pub struct ExecCommand {
+	template: String,
+	args: Vec<String>,
+	mode: ExecMode,
+}
+
+impl ExecCommand {
+	/// Splits the template into arguments the way a shell does, without expanding anything.
+	pub fn parse(template: &str) -> Result<Self, String> {
+		let invalid = || t!("error-invalid-exec-template", template = template).to_string();
+		let args = shell_words::split(template).map_err(|e| format!("{}: {e}", invalid()))?;
+		if args.is_empty() {
+			return Err(invalid())
+		}
+		let uses = |placeholder| args.iter().any(|arg| arg.contains(placeholder));
+		let mode = match (uses(IN_PLACEHOLDER), uses(OUT_PLACEHOLDER), uses(FILE_PLACEHOLDER), uses(FILES_PLACEHOLDER)) {
+			(true, true, false, false) => ExecMode::InOut,
+			(false, false, true, false) => ExecMode::InPlace,
+			// The files are separate arguments, so they cannot be a part of another one
+			(false, false, false, true) if args.iter().all(|arg| arg == FILES_PLACEHOLDER || !arg.contains(FILES_PLACEHOLDER)) => ExecMode::Batch,
+			(false, false, false, false) => ExecMode::Stdio,
+			_ => return Err(invalid()),
+		};
+		Ok(Self { template: template.to_string(), args, mode })
+	}
+
+	pub fn template(&self) -> &str {
+		&self.template
+	}
+
+	/// Name of the program, as it appears in the error messages.
+	pub fn name(&self) -> &str {
+		&self.args[0]
+	}
+
+	pub fn is_batch(&self) -> bool {
+		self.mode == ExecMode::Batch
+	}
+
+	fn command(&self, placeholders: &[(&str, &Path)]) -> Command {
+		let mut args = self.args.iter().map(|arg| {
+			match placeholders.iter().find(|(placeholder, _)| arg == placeholder) {
+				Some((_, path)) => path.as_os_str().to_os_string(),
+				None => placeholders.iter()
+					.fold(arg.clone(), |arg, (placeholder, path)| arg.replace(placeholder, &path.to_string_lossy()))
+					.into(),
+			}
+		});
+		let mut command = Command::new(args.next().unwrap());
+		command.args(args);
+		command
+	}
+
+	/// Runs the command on a single file. `timeout` limits each run of the command.
+	pub fn run_file(&self, path: &Path, timeout: Option<Duration>) -> io::Result<()> {
+		match self.mode {
+			ExecMode::InOut => {
+				let out_path = output_path_for(path);
+				let result = (|| {
+					command::run(&mut self.command(&[(IN_PLACEHOLDER, path), (OUT_PLACEHOLDER, &out_path)]), self.name(), None, timeout)?;
+					write_atomically(path, fs::read(&out_path)?)
+				})();
+				let _ = fs::remove_file(&out_path);
+				result
+			}
+			ExecMode::InPlace => command::run(&mut self.command(&[(FILE_PLACEHOLDER, path)]), self.name(), None, timeout).map(|_| ()),
+			ExecMode::Batch => self.run_batch(&[path], timeout),
+			ExecMode::Stdio => {
+				let output = command::run(&mut self.command(&[]), self.name(), Some(fs::read(path)?), timeout)?;
+				if output.is_empty() {
+					return Err(io::Error::other(t!("error-command-no-output", command = self.name())))
+				}
+				write_atomically(path, output)
+			}
+		}
+	}
+
+	/// Runs a batch command on the files, split into batches that fit into the command line,
+	/// which run in parallel. `timeout` limits each run of the command.
+	pub fn run_batch(&self, paths: &[&Path], timeout: Option<Duration>) -> io::Result<()> {
+		let fixed_args_length = args_length(self.args.iter().filter(|arg| *arg != FILES_PLACEHOLDER).map(OsStr::new));
+		batches(paths, MAX_ARGS_LENGTH.saturating_sub(fixed_args_length)).into_par_iter().try_for_each(|batch| {
+			let mut command = Command::new(self.name());
+			for arg in &self.args[1..] {
+				if arg == FILES_PLACEHOLDER {
+					command.args(batch);
+				} else {
+					command.arg(arg);
+				}
+			}
+			command::run(&mut command, self.name(), None, timeout).map(|_| ())
+		})
+	}
+}
+
+/// A sibling of the file for the command to write the result to, with the same extension,
+/// as some tools choose the output format by it.
+fn output_path_for(path: &Path) -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	path.with_file_name(format!(".svgc-exec-{}-{}-{file_name}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_exec_command() {
+		assert_eq!(ExecCommand::parse("scour {in} {out}").unwrap().mode, ExecMode::InOut);
+		assert_eq!(ExecCommand::parse("tool --input={file}").unwrap().mode, ExecMode::InPlace);
+		assert_eq!(ExecCommand::parse("tool -q {files}").unwrap().mode, ExecMode::Batch);
+		assert_eq!(ExecCommand::parse("'my tool' --stdin").unwrap().name(), "my tool");
+		assert_eq!(ExecCommand::parse("tool --stdin").unwrap().mode, ExecMode::Stdio);
+		assert!(ExecCommand::parse("tool {in}").is_err());
+		assert!(ExecCommand::parse("tool {file} {files}").is_err());
+		assert!(ExecCommand::parse("tool --files={files}").is_err());
+		assert!(ExecCommand::parse("").is_err());
+	}
+}
\ No newline at end of file
@@ -14,85 +14,188 @@
 //! You should have received a copy of the GNU Affero General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{env, fs};
+use std::{env, fs, process};
+use std::collections::HashSet;
 use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use chrono::Local;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use rayon::prelude::*;
 use rust_i18n::t;
 
+use crate::atomic_write::write_atomically_with;
+use crate::backup::{self, Manifest, ManifestEntry};
+use crate::cancel;
 use crate::default_opt::default_optimize;
-use crate::svgo::run_svgo;
-use crate::svgz::compress_to_svgz;
+use crate::exec::ExecCommand;
+use crate::preserve::Preserve;
+use crate::report::{FileReport, FileStatus};
+use crate::savings::MinSavings;
+use crate::svgo::{run_svgo, SvgoOptions};
+use crate::svgz::{compress_to_svgz, decompress_in_place, is_gzip_file, write_precompressed, PrecompressedFormat, SvgzCompression};
 
 fn unique_timestamp() -> String {
 	Local::now().format("%Y-%m-%d_%H-%M-%S_%f").to_string()
 }
 
+const TEMP_DIR_PREFIX: &str = "svgc_temp_files_";
+
+/// Holds the id of the process using a temporary directory, so `clean-backups` leaves it alone.
+const OWNER_PID_FILE_NAME: &str = ".svgc-pid";
+
+/// Age after which a temporary directory, whose owner process cannot be checked, is left behind.
+const LEFTOVER_TEMP_DIR_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
 fn generate_temp_dir_name() -> String {
-	format!("svgc_temp_files_{}", unique_timestamp())
+	format!("{TEMP_DIR_PREFIX}{}", unique_timestamp())
 }
 
-fn try_create_temp_dir(path: &PathBuf, name: &str) -> io::Result<PathBuf> {
+fn generate_backup_dir_name() -> String {
+	format!("svgc_backup_{}", unique_timestamp())
+}
+
+/// Creates a new directory only the current user can access, failing if it already exists,
+/// as it may be in a directory shared with other users.
+fn try_create_temp_dir(path: &Path, name: &str) -> io::Result<PathBuf> {
+	fs::create_dir_all(path)?;
 	let temp_dir = path.join(name);
-	fs::create_dir_all(&temp_dir)?;
+	let mut builder = fs::DirBuilder::new();
+	#[cfg(unix)]
+	std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+	builder.create(&temp_dir)?;
 	Ok(temp_dir)
 }
 
-pub fn create_temp_dir() -> Option<PathBuf> {
+/// Directories to create temporary directories in, in the order of preference.
+fn temp_dir_locations(preferred: Option<&Path>) -> Vec<PathBuf> {
+	[
+		preferred.map(Path::to_path_buf),
+		Some(env::temp_dir()),
+		directories::ProjectDirs::from("org", "pasabanov", "svgc").map(|dirs| dirs.data_dir().to_path_buf()),
+		dirs::home_dir(),
+	].into_iter().flatten().collect()
+}
+
+pub fn create_temp_dir(preferred: Option<&Path>) -> Option<PathBuf> {
 	let temp_dir_name = generate_temp_dir_name();
 
 	// Trying to generate temporary directory in some of these directories
-	let directories = [
-		|| { // current directory, checking that is not temporary
-			let current = fs::canonicalize(env::current_dir().ok()?).ok()?;
-			let temp = fs::canonicalize(env::temp_dir()).ok()?;
-			if current != temp { Some(current) } else { None }
-		},
-		|| dirs::home_dir(),
-		|| directories::ProjectDirs::from("org", "pasabanov", "svgc").map(|dirs| dirs.data_dir().to_path_buf()),
-	];
-
-	for (i, get_dir) in directories.iter().enumerate() {
-		if let Some(dir) = get_dir() {
-			if let Ok(temp_dir) = try_create_temp_dir(&dir, &temp_dir_name) {
-				return Some(temp_dir);
-			}
-			eprintln!("{}",
-				t!("could-not-create-temp-dir-in-dir",
-					dir = dir.display(),
-					suffix = if i < directories.len() - 1 { " Trying next." } else { "" }
-				)
-			);
+	let directories = temp_dir_locations(preferred);
+
+	for (i, dir) in directories.iter().enumerate() {
+		let temp_dir = try_create_temp_dir(dir, &temp_dir_name)
+			.and_then(|temp_dir| fs::write(temp_dir.join(OWNER_PID_FILE_NAME), process::id().to_string()).map(|_| temp_dir));
+		if let Ok(temp_dir) = temp_dir {
+			return Some(temp_dir);
 		}
+		eprintln!("{}",
+			t!("could-not-create-temp-dir-in-dir",
+				dir = dir.display(),
+				suffix = if i < directories.len() - 1 { " Trying next." } else { "" }
+			)
+		);
 	}
 
 	None
 }
 
+/// Whether the run that created the temporary directory may still be using it.
+///
+/// Directories without a live owner to check, as the ones of previous versions, are in use until they are old enough.
+fn is_temp_dir_in_use(dir: &Path) -> bool {
+	let owner_pid = fs::read_to_string(dir.join(OWNER_PID_FILE_NAME)).ok()
+		.and_then(|pid| pid.trim().parse::<u32>().ok());
+	match owner_pid {
+		#[cfg(unix)]
+		Some(pid) => is_process_alive(pid),
+		_ => dir.metadata().and_then(|metadata| metadata.modified()).ok()
+			.and_then(|modified| modified.elapsed().ok())
+			.is_none_or(|age| age < LEFTOVER_TEMP_DIR_AGE),
+	}
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+	// Signal 0 only checks that the process exists, a process of another user cannot be signaled
+	let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+	result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Finds temporary directories left behind by runs that failed to restore files.
+///
+/// Also looks in the current directory, where previous versions created them. Directories of
+/// runs still in progress are not included.
+pub fn find_leftover_temp_dirs(preferred: Option<&Path>) -> Vec<PathBuf> {
+	let mut locations = temp_dir_locations(preferred);
+	locations.extend(env::current_dir().ok());
+
+	let mut temp_dirs: Vec<PathBuf> = locations.iter()
+		.filter_map(|location| fs::read_dir(location).ok())
+		.flat_map(|entries| entries.flatten())
+		.filter(|entry| entry.file_name().to_string_lossy().starts_with(TEMP_DIR_PREFIX))
+		.filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+		.filter_map(|entry| fs::canonicalize(entry.path()).ok())
+		.filter(|dir| !is_temp_dir_in_use(dir))
+		.collect();
+	temp_dirs.sort();
+	temp_dirs.dedup();
+	temp_dirs
+}
+
+/// Creates a directory for a persistent backup run inside `backups_dir`.
+pub fn create_backup_dir(backups_dir: &Path) -> io::Result<PathBuf> {
+	try_create_temp_dir(backups_dir, &generate_backup_dir_name())
+}
+
+/// Checks the name of the file only. The extension is case-insensitive, as in `icon.SVG`.
+pub fn has_svg_extension(path: &Path) -> bool {
+	path.file_name().is_some_and(|name| name.to_string_lossy().to_ascii_lowercase().ends_with(".svg"))
+}
+
+pub fn has_svgz_extension(path: &Path) -> bool {
+	path.file_name().is_some_and(|name| name.to_string_lossy().to_ascii_lowercase().ends_with(".svgz"))
+}
+
 pub fn is_svg_file(path: &Path) -> bool {
-	path.is_file() && (path.extension() == Some("svg".as_ref()) || path.file_name() == Some(".svg".as_ref()))
+	path.is_file() && (has_svg_extension(path) || has_svgz_extension(path))
 }
 
 struct SvgFile {
 	original_path: PathBuf,
 	backup_path: PathBuf,
 	result_path: Option<PathBuf>,
+	original_metadata: fs::Metadata,
 	original_size: u64,
 	result_size: Option<u64>,
+	/// Whether the original file is gzipped, so the result has to be compressed back.
+	compressed_input: bool,
+	passes: Vec<&'static str>,
+	error: Option<String>,
+	/// Size of the result that was dropped, as it did not save enough.
+	skipped_size: Option<u64>,
+	/// Precompressed copies of the result, such as `icon.svg.gz`.
+	precompressed_paths: Vec<PathBuf>,
+	/// Whether the file was restored, as processing of the group failed.
+	rolled_back: bool,
 }
 
 #[allow(dead_code)]
 impl SvgFile {
-	pub fn new(original_path: PathBuf, backup_dir: &Path) -> io::Result<Self> {
+	/// `index` keeps backup names unique when files with the same name are backed up concurrently.
+	pub fn new(original_path: PathBuf, index: usize, backup_dir: &Path) -> io::Result<Self> {
 		if is_svg_file(&original_path) {
-			let original_size = original_path.metadata()?.len();
+			let original_metadata = original_path.metadata()?;
+			let original_size = original_metadata.len();
 			let backup_path = backup_dir.join(
 				format!(
-					"{}_{}.{}",
+					"{}_{}_{}.{}",
 					original_path.file_stem()
 						.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, t!("could-not-get-file-name", path = original_path.display())))?
 						.to_string_lossy(),
+					index,
 					unique_timestamp(),
 					original_path.extension()
 						.unwrap_or_default()
@@ -100,29 +203,71 @@ impl SvgFile {
 				)
 			);
 			fs::copy(&original_path, &backup_path)?;
+			let compressed_input = has_svgz_extension(&original_path) || is_gzip_file(&original_path)?;
 			Ok(Self {
 				original_path,
 				backup_path,
 				result_path: None,
+				original_metadata,
 				original_size,
 				result_size: None,
+				compressed_input,
+				passes: Vec::new(),
+				error: None,
+				skipped_size: None,
+				precompressed_paths: Vec::new(),
+				rolled_back: false,
 			})
 		} else {
 			Err(io::Error::new(io::ErrorKind::NotFound, t!("path-not-svg", path = original_path.display())))
 		}
 	}
 
-	pub fn apply_default_optimizations(&self, remove_fill: bool) -> io::Result<()> {
-		default_optimize(&self.original_path, remove_fill)
+	pub fn decompress(&mut self) -> io::Result<()> {
+		self.record_pass("decompress", decompress_in_place(&self.original_path))
 	}
 
-	pub fn compress(&mut self) -> io::Result<()> {
-		self.result_path = Some(compress_to_svgz(&self.original_path)?);
-		Ok(())
+	pub fn apply_default_optimizations(&mut self, remove_fill: bool) -> io::Result<()> {
+		self.record_pass("default", default_optimize(&self.original_path, remove_fill))
+	}
+
+	pub fn compress(&mut self, compression: SvgzCompression, keep_svg: bool) -> io::Result<()> {
+		let result = compress_to_svgz(&self.original_path, compression, keep_svg).map(|path| self.result_path = Some(path));
+		self.record_pass("svgz", result)
+	}
+
+	pub fn precompress(&mut self, formats: &[PrecompressedFormat], compression: SvgzCompression) -> io::Result<()> {
+		let result = write_precompressed(&self.original_path, formats, compression).map(|paths| self.precompressed_paths = paths);
+		self.record_pass("precompress", result)
+	}
+
+	pub fn preserve_attributes(&self, preserve: &Preserve) -> io::Result<()> {
+		self.produced_paths().chain(self.precompressed_paths.iter().map(PathBuf::as_path))
+			.try_for_each(|path| preserve.apply(&self.original_metadata, path))
+	}
+
+	/// The original file, if it was optimized and kept next to a different result file.
+	fn kept_original(&self) -> Option<&Path> {
+		self.result_path.as_ref()
+			.filter(|result_path| **result_path != self.original_path && self.original_path.exists())
+			.map(|_| self.original_path.as_path())
+	}
+
+	/// The result file, and the original one if it was kept.
+	fn produced_paths(&self) -> impl Iterator<Item = &Path> {
+		[Some(self.result_path.as_deref().unwrap_or(&self.original_path)), self.kept_original()].into_iter().flatten()
+	}
+
+	fn record_pass(&mut self, pass: &'static str, result: io::Result<()>) -> io::Result<()> {
+		match &result {
+			Ok(()) => self.passes.push(pass),
+			Err(e) => self.error = Some(e.to_string()),
+		}
+		result
 	}
 
 	pub fn calculate_result_size(&mut self) -> io::Result<()> {
-		if self.result_size != None {
+		if self.result_size.is_some() {
 			return Ok(())
 		}
 		let path = self.result_path.as_deref().unwrap_or(&self.original_path);
@@ -130,8 +275,41 @@ impl SvgFile {
 		Ok(())
 	}
 
-	pub fn restore(&self) -> io::Result<()> {
-		fs::copy(&self.backup_path, &self.original_path).map(|_| ())
+	/// Puts the original file back and removes the result file if it is a different one.
+	pub fn restore(&mut self) -> io::Result<()> {
+		let mut backup = fs::File::open(&self.backup_path)?;
+		write_atomically_with(&self.original_path, |writer| io::copy(&mut backup, writer).map(|_| ()))?;
+		Preserve::restore().apply(&self.original_metadata, &self.original_path)?;
+		if let Some(result_path) = self.result_path.take() {
+			if result_path != self.original_path && result_path.try_exists()? {
+				fs::remove_file(result_path)?;
+			}
+		}
+		for path in self.precompressed_paths.drain(..) {
+			if path.try_exists()? {
+				fs::remove_file(path)?;
+			}
+		}
+		self.result_size = None;
+		self.passes.clear();
+		Ok(())
+	}
+
+	/// Keeps the original file if the result does not save at least `min_savings`.
+	pub fn skip_if_insufficient_savings(&mut self, min_savings: MinSavings) -> io::Result<()> {
+		self.calculate_result_size()?;
+		let result_size = self.result_size.unwrap();
+		// A kept original is the main result, and the compressed one is an addition to it
+		let produced_size = match self.kept_original() {
+			Some(path) => path.metadata()?.len(),
+			None => result_size,
+		};
+		if min_savings.is_met(self.original_size, produced_size) {
+			return Ok(())
+		}
+		self.restore()?;
+		self.skipped_size = Some(result_size);
+		Ok(())
 	}
 
 	pub fn original_path(&self) -> &Path {
@@ -153,24 +331,93 @@ impl SvgFile {
 	pub fn result_size(&self) -> Option<u64> {
 		self.result_size
 	}
+
+	pub fn passes(&self) -> &[&'static str] {
+		&self.passes
+	}
+
+	pub fn error(&self) -> Option<&str> {
+		self.error.as_deref()
+	}
+
+	pub fn skipped_size(&self) -> Option<u64> {
+		self.skipped_size
+	}
+
+	pub fn is_compressed_input(&self) -> bool {
+		self.compressed_input
+	}
+
+	pub fn is_compressed(&self) -> bool {
+		self.passes.contains(&"svgz")
+	}
+
+	pub fn is_failed(&self) -> bool {
+		self.error.is_some()
+	}
+
+	pub fn status(&self) -> FileStatus {
+		if self.is_failed() {
+			FileStatus::Failed
+		} else if self.skipped_size.is_some() {
+			FileStatus::Skipped
+		} else if self.rolled_back {
+			FileStatus::RolledBack
+		} else {
+			FileStatus::Optimized
+		}
+	}
 }
 
 pub struct SvgFileGroup {
 	files: Vec<SvgFile>,
 	backup_dir: PathBuf,
 	auto_delete_backups: bool,
+	keep_going: bool,
+	manifest: Option<Manifest>,
+	/// Files left as they are, as they were already optimized, with their sizes.
+	unchanged_files: Vec<(PathBuf, u64)>,
 }
 
 #[allow(dead_code)]
 impl SvgFileGroup {
-	pub fn new(paths: Vec<PathBuf>, auto_delete_backups: bool) -> io::Result<Self> {
-		let backup_dir = create_temp_dir()
+	pub fn new(paths: Vec<PathBuf>, temp_dir: Option<&Path>, auto_delete_backups: bool) -> io::Result<Self> {
+		let backup_dir = create_temp_dir(temp_dir)
 			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, t!("could-not-create-temporary-directory")))?;
+		Self::with_backup_dir(paths, backup_dir, auto_delete_backups, false)
+	}
+
+	/// Creates a group whose backups outlive the program, described by a manifest in a new directory inside `backups_dir`.
+	pub fn new_persistent(paths: Vec<PathBuf>, backups_dir: &Path) -> io::Result<Self> {
+		let backup_dir = create_backup_dir(backups_dir)?;
+		Self::with_backup_dir(paths, backup_dir, false, true)
+	}
+
+	fn with_backup_dir(paths: Vec<PathBuf>, backup_dir: PathBuf, auto_delete_backups: bool, persistent: bool) -> io::Result<Self> {
 		fn initialize_files(paths: Vec<PathBuf>, backup_dir: &Path) -> io::Result<Vec<SvgFile>> {
-			paths.into_iter().map(|path| SvgFile::new(path, backup_dir)).collect()
+			paths.into_par_iter().enumerate().map(|(i, path)| SvgFile::new(path, i, backup_dir)).collect()
 		}
-		match initialize_files(paths, &backup_dir) {
-			Ok(files) => Ok(Self {files, backup_dir, auto_delete_backups}),
+		fn initialize_manifest(files: &[SvgFile], backup_dir: &Path) -> io::Result<Manifest> {
+			let manifest = Manifest {
+				version: env!("CARGO_PKG_VERSION").to_string(),
+				created: Local::now().to_rfc3339(),
+				files: files.par_iter().map(|file| Ok(ManifestEntry {
+					original_path: file.original_path.clone(),
+					backup_file: file.backup_path.strip_prefix(backup_dir).unwrap_or(&file.backup_path).to_path_buf(),
+					result_path: None,
+					precompressed_paths: Vec::new(),
+					sha256: backup::sha256_file(&file.backup_path)?,
+				})).collect::<io::Result<_>>()?,
+			};
+			manifest.write(backup_dir)?;
+			Ok(manifest)
+		}
+		let result = initialize_files(paths, &backup_dir).and_then(|files| {
+			let manifest = if persistent { Some(initialize_manifest(&files, &backup_dir)?) } else { None };
+			Ok((files, manifest))
+		});
+		match result {
+			Ok((files, manifest)) => Ok(Self {files, backup_dir, auto_delete_backups, keep_going: false, manifest, unchanged_files: Vec::new()}),
 			Err(e) => {
 				if let Err(cleanup_error) = fs::remove_dir_all(&backup_dir) {
 					eprintln!("{}", t!("failed-to-delete-temp-dir", dir = backup_dir.display(), error = cleanup_error));
@@ -180,22 +427,79 @@ impl SvgFileGroup {
 		}
 	}
 
-	pub fn apply_default_optimizations(&self, remove_fill: bool) -> io::Result<()> {
-		for file in &self.files {
-			file.apply_default_optimizations(remove_fill)?
-		}
-		Ok(())
+	/// Applies `stage` to every file that has not failed yet.
+	///
+	/// In keep-going mode a failed file is restored on its own and the rest are processed further,
+	/// otherwise the first error is returned.
+	fn for_each_file(&mut self, stage: impl Fn(&mut SvgFile) -> io::Result<()> + Sync) -> io::Result<()> {
+		let keep_going = self.keep_going;
+		self.files.par_iter_mut().filter(|file| !file.is_failed()).try_for_each(|file| {
+			// Cancellation stops the whole group, so all the files are restored
+			if cancel::is_cancelled() {
+				return Err(cancel::cancelled_error())
+			}
+			match stage(file) {
+				Err(_) if keep_going && !cancel::is_cancelled() => file.restore(),
+				result => result,
+			}
+		})
 	}
 
-	pub fn apply_svgo(&self, svgo_path: &Path) -> io::Result<()> {
-		run_svgo(self.files.iter().map(|f| f.original_path.as_path()), svgo_path)
+	/// Decompresses gzipped files in place, so the rest of the passes work with plain SVG.
+	pub fn decompress_inputs(&mut self) -> io::Result<()> {
+		self.for_each_file(|file| if file.is_compressed_input() { file.decompress() } else { Ok(()) })
 	}
 
-	pub fn compress(&mut self) -> io::Result<()> {
-		for file in &mut self.files {
-			file.compress()?
+	pub fn apply_default_optimizations(&mut self, remove_fill: bool) -> io::Result<()> {
+		self.for_each_file(|file| file.apply_default_optimizations(remove_fill))
+	}
+
+	pub fn apply_svgo(&mut self, svgo: &SvgoOptions) -> io::Result<()> {
+		let paths: Vec<_> = self.files.iter().filter(|f| !f.is_failed()).map(|f| f.original_path.as_path()).collect();
+		match run_svgo(&paths, svgo) {
+			Ok(()) => self.for_each_file(|file| file.record_pass("svgo", Ok(()))),
+			// SVGO does not tell which files it failed on, so each file is run separately to find them
+			Err(_) => self.for_each_file(|file| {
+				let result = run_svgo(&[&file.original_path], svgo);
+				file.record_pass("svgo", result)
+			}),
 		}
-		Ok(())
+	}
+
+	pub fn apply_exec(&mut self, command: &ExecCommand, timeout: Option<Duration>) -> io::Result<()> {
+		if command.is_batch() {
+			let paths: Vec<_> = self.files.iter().filter(|f| !f.is_failed()).map(|f| f.original_path.as_path()).collect();
+			if command.run_batch(&paths, timeout).is_ok() {
+				return self.for_each_file(|file| file.record_pass("exec", Ok(())))
+			}
+			// As with SVGO, the failed files are found by running the command on each file separately
+		}
+		self.for_each_file(|file| {
+			let result = command.run_file(&file.original_path, timeout);
+			file.record_pass("exec", result)
+		})
+	}
+
+	pub fn compress(&mut self, compression: SvgzCompression, keep_svg: bool) -> io::Result<()> {
+		self.for_each_file(|file| file.compress(compression, keep_svg))
+	}
+
+	/// Compresses the files that were gzipped originally and have not been compressed yet.
+	pub fn recompress_inputs(&mut self, compression: SvgzCompression) -> io::Result<()> {
+		self.for_each_file(|file| if file.is_compressed_input() && !file.is_compressed() { file.compress(compression, false) } else { Ok(()) })
+	}
+
+	/// Writes precompressed copies of the files, except the ones that are compressed themselves.
+	pub fn precompress(&mut self, formats: &[PrecompressedFormat], compression: SvgzCompression) -> io::Result<()> {
+		self.for_each_file(|file| if file.is_compressed_input() { Ok(()) } else { file.precompress(formats, compression) })
+	}
+
+	pub fn skip_insufficient_savings(&mut self, min_savings: MinSavings) -> io::Result<()> {
+		self.for_each_file(|file| file.skip_if_insufficient_savings(min_savings))
+	}
+
+	pub fn preserve_attributes(&mut self, preserve: Preserve) -> io::Result<()> {
+		self.for_each_file(|file| file.preserve_attributes(&preserve))
 	}
 
 	pub fn print_summary(&mut self) -> io::Result<()> {
@@ -204,6 +508,7 @@ impl SvgFileGroup {
 		let mut total_after: u64 = 0;
 
 		let current_dir = env::current_dir().ok();
+		let current_dir = current_dir.as_deref();
 
 		for file in &mut self.files {
 			file.calculate_result_size()?;
@@ -214,17 +519,14 @@ impl SvgFileGroup {
 			total_before += original_size;
 			total_after += result_size;
 
-			let size_diff = original_size.saturating_sub(result_size);
+			let size_diff = original_size as i64 - result_size as i64;
 			let size_diff_percent = (size_diff as f64 / original_size as f64) * 100.0;
 
 			let original_path = file.original_path();
 			let result_path = file.result_path().unwrap_or(original_path);
 
-			let (relative_file, relative_final_path) = if let Some(ref dir) = current_dir {
-				(original_path.strip_prefix(dir).unwrap_or(original_path), result_path.strip_prefix(dir).unwrap_or(&result_path))
-			} else {
-				(original_path, result_path)
-			};
+			let relative_file = relative_to(original_path, current_dir);
+			let relative_final_path = relative_to(result_path, current_dir);
 
 			let file_name_display = if relative_final_path != relative_file {
 				format!("{} -> {}", relative_file.display(), relative_final_path.display())
@@ -232,33 +534,134 @@ impl SvgFileGroup {
 				relative_file.display().to_string()
 			};
 
+			if let Some(error) = file.error() {
+				let failed_str = t!("file-failed-and-restored", error = error);
+				if io::stdout().is_terminal() {
+					println!("{file_name_display}:\n\x1b[31m{failed_str}\x1b[0m\n"); // Red
+				} else {
+					println!("{file_name_display}:\n{failed_str}\n");
+				}
+				continue
+			}
+
+			if let Some(skipped_size) = file.skipped_size() {
+				println!("{file_name_display}:\n{}\n", t!("file-skipped", size = skipped_size));
+				continue
+			}
+
 			let percent_str = if size_diff_percent > 0.0 && io::stdout().is_terminal() {
 				format!("\x1b[32m{:.2}%\x1b[0m", size_diff_percent) // Green
 			} else {
-				format!("{:.2}%", size_diff_percent)
+				format!("{:.2}%", size_diff_percent.abs())
 			};
+			// A result can only grow when the original file is kept next to it
+			let sign = if size_diff < 0 { '+' } else { '-' };
+
+			println!("{file_name_display}:\n{original_size} {sign} {percent_str} = {result_size} {}\n", t!("bytes"));
+		}
 
-			println!("{file_name_display}:\n{original_size} - {percent_str} = {result_size} {}\n", t!("bytes"));
+		for (path, size) in &self.unchanged_files {
+			total_before += size;
+			total_after += size;
+			println!("{}:\n{}\n", relative_to(path, current_dir).display(), t!("file-unchanged"));
 		}
 
-		let total_diff = total_before.saturating_sub(total_after);
-		let total_diff_percent = (total_diff as f64 / total_before as f64) * 100.0;
+		let total_change = total_after as i64 - total_before as i64;
+		let total_change_percent = (total_change as f64 / total_before as f64) * 100.0;
 
 		let total_str = t!("total");
 		let bytes_str = t!("bytes");
 
-		println!("{total_str}: {total_before} -> {total_after} {bytes_str} (-{total_diff} {bytes_str}, -{:.2}%)", total_diff_percent);
+		println!("{total_str}: {total_before} -> {total_after} {bytes_str} ({total_change:+} {bytes_str}, {:+.2}%)", total_change_percent);
+
+		if !self.unchanged_files.is_empty() {
+			println!("{}", t!("files-unchanged-count", count = self.unchanged_files.len()));
+		}
+		let skipped_count = self.files.iter().filter(|file| file.skipped_size().is_some()).count();
+		if skipped_count > 0 {
+			println!("{}", t!("files-skipped-count", count = skipped_count));
+		}
+		let failed_count = self.failed_files().count();
+		if failed_count > 0 {
+			println!("{}", t!("files-failed-count", count = failed_count));
+		}
 
 		Ok(())
 	}
 
-	pub fn restore_files(&self) -> io::Result<()> {
-		for file in &self.files {
+	pub fn file_reports(&mut self) -> io::Result<Vec<FileReport<'_>>> {
+		let current_dir = env::current_dir().ok();
+		self.files.par_iter_mut().try_for_each(SvgFile::calculate_result_size)?;
+		let unchanged_reports = self.unchanged_files.iter().map(|(path, size)| FileReport {
+			status: FileStatus::Unchanged,
+			original_path: relative_to(path, current_dir.as_deref()),
+			result_path: relative_to(path, current_dir.as_deref()),
+			original_size: *size,
+			result_size: *size,
+			passes: &[],
+			error: None,
+		});
+		Ok(self.files.iter().map(|file| {
+			FileReport {
+				status: file.status(),
+				original_path: relative_to(file.original_path(), current_dir.as_deref()),
+				result_path: relative_to(file.result_path().unwrap_or(file.original_path()), current_dir.as_deref()),
+				original_size: file.original_size(),
+				result_size: file.result_size().unwrap(),
+				passes: file.passes(),
+				error: file.error(),
+			}
+		}).chain(unchanged_reports).collect())
+	}
+
+	pub fn add_unchanged_files(&mut self, files: Vec<(PathBuf, u64)>) {
+		self.unchanged_files.extend(files);
+	}
+
+	/// Hashes of the files successfully produced by the run.
+	pub fn result_hashes(&self) -> Vec<io::Result<String>> {
+		self.files.par_iter()
+			.filter(|file| !file.is_failed())
+			.flat_map_iter(|file| file.produced_paths().map(backup::sha256_file).collect::<Vec<_>>())
+			.collect()
+	}
+
+	pub fn restore_files(&mut self) -> io::Result<()> {
+		self.files.par_iter_mut().try_for_each(|file| {
 			file.restore()?;
+			file.rolled_back = true;
+			Ok(())
+		})
+	}
+
+	pub fn failed_files(&self) -> impl Iterator<Item = (&Path, &str)> {
+		self.files.iter().filter_map(|file| Some((file.original_path(), file.error()?)))
+	}
+
+	pub fn is_keep_going(&self) -> bool {
+		self.keep_going
+	}
+
+	pub fn enable_keep_going(&mut self) {
+		self.keep_going = true;
+	}
+
+	/// Records the result paths in the manifest of a persistent backup.
+	pub fn update_manifest(&mut self) -> io::Result<()> {
+		if let Some(manifest) = &mut self.manifest {
+			for (entry, file) in manifest.files.iter_mut().zip(&self.files) {
+				entry.result_path = file.result_path.clone();
+				entry.precompressed_paths = file.precompressed_paths.clone();
+			}
+			manifest.write(&self.backup_dir)?;
 		}
 		Ok(())
 	}
 
+	pub fn is_persistent(&self) -> bool {
+		self.manifest.is_some()
+	}
+
 	pub fn backup_dir(&self) -> &Path {
 		&self.backup_dir
 	}
@@ -293,30 +696,139 @@ impl Drop for SvgFileGroup {
 	}
 }
 
-pub fn find_svg_files(paths: &[PathBuf], recursive: bool) -> io::Result<Vec<PathBuf>> {
+fn relative_to<'a>(path: &'a Path, dir: Option<&Path>) -> &'a Path {
+	dir.and_then(|dir| path.strip_prefix(dir).ok()).unwrap_or(path)
+}
+
+/// Project-level file listing SVG files to skip, using the `.gitignore` syntax.
+pub const SVGC_IGNORE_FILE_NAME: &str = ".svgcignore";
+
+/// Rules for finding SVG files in the given paths.
+pub struct FindOptions {
+	pub recursive: bool,
+	/// Whether to honor `.gitignore` (including nested and global ones), `.ignore` and `.svgcignore` files.
+	pub use_ignore_files: bool,
+	/// Whether to follow symbolic links inside directories. Otherwise they are skipped with a warning.
+	pub follow_symlinks: bool,
+	/// Whether to take hidden files and directories (starting with a dot) from directories.
+	pub hidden: bool,
+	/// Maximum depth of recursion, where 1 means only the files directly in the given directories.
+	pub max_depth: Option<usize>,
+	/// If set, only files matching these patterns are taken from directories.
+	pub include: Option<GlobSet>,
+	/// Files and directories matching these patterns are skipped.
+	pub exclude: GlobSet,
+}
 
-	fn find_append_svg_files(container: &mut Vec<PathBuf>, path: &PathBuf, recursive: bool) -> io::Result<()> {
-		if path.is_file() {
-			if path.extension().and_then(|e| e.to_str()) == Some("svg") {
-				container.push(path.clone());
+impl FindOptions {
+	/// Patterns are matched against paths relative to the directory given to the program.
+	pub fn new(recursive: bool, use_ignore_files: bool, follow_symlinks: bool, hidden: bool, max_depth: Option<usize>, include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+		fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+			let mut builder = GlobSetBuilder::new();
+			for pattern in patterns {
+				builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
 			}
-			return Ok(())
-		} else if !path.is_dir() {
-			return Ok(())
+			builder.build()
 		}
-		for entry in fs::read_dir(path)? {
-			let entry = entry?;
+		Ok(Self {
+			recursive,
+			use_ignore_files,
+			follow_symlinks,
+			hidden,
+			max_depth,
+			include: if include.is_empty() { None } else { Some(build_glob_set(include)?) },
+			exclude: build_glob_set(exclude)?,
+		})
+	}
+
+	fn is_excluded(&self, relative_path: &Path) -> bool {
+		self.exclude.is_match(relative_path)
+	}
+
+	fn is_included(&self, relative_path: &Path) -> bool {
+		self.include.as_ref().is_none_or(|include| include.is_match(relative_path)) && !self.is_excluded(relative_path)
+	}
+}
+
+/// Leaves out the `.svgz` files that are produced again by compressing their `.svg` siblings, also found.
+///
+/// The extensions are compared case-insensitively, so `icon.SVGZ` is left out for `icon.svg`.
+pub fn skip_svgz_siblings(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+	let svg_stems: HashSet<_> = paths.iter()
+		.filter(|path| has_svg_extension(path))
+		.map(|path| path.with_extension(""))
+		.collect();
+	paths.into_iter()
+		.filter(|path| !(has_svgz_extension(path) && svg_stems.contains(&path.with_extension(""))))
+		.collect()
+}
+
+/// Files given explicitly are always taken, patterns only filter the contents of directories.
+pub fn find_svg_files(paths: &[PathBuf], options: &FindOptions) -> io::Result<Vec<PathBuf>> {
+
+	fn find_append_svg_files(container: &mut Vec<PathBuf>, root: &Path, options: &FindOptions) -> io::Result<()> {
+		let mut walker = WalkBuilder::new(root);
+		walker
+			.standard_filters(options.use_ignore_files)
+			.hidden(!options.hidden)
+			.follow_links(options.follow_symlinks)
+			.max_depth(if options.recursive { options.max_depth } else { Some(1) });
+		if options.use_ignore_files {
+			walker.add_custom_ignore_filename(SVGC_IGNORE_FILE_NAME);
+		}
+		let filter_root = root.to_path_buf();
+		let exclude = options.exclude.clone();
+		let skip_git_dirs = options.use_ignore_files;
+		walker.filter_entry(move |entry| {
+			// Excluded directories are not descended into
+			let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+			entry.depth() == 0 || !is_dir || !(skip_git_dirs && entry.file_name() == ".git"
+				|| exclude.is_match(entry.path().strip_prefix(&filter_root).unwrap_or(entry.path())))
+		});
+
+		for entry in walker.build() {
+			let entry = match entry {
+				Ok(entry) => entry,
+				Err(e) if is_symlink_loop(&e) => {
+					eprintln!("{}", t!("skipping-symlink-loop", error = e));
+					continue
+				}
+				Err(e) => return Err(io::Error::other(e)),
+			};
 			let path = entry.path();
-			if path.is_file() || recursive && path.is_dir() {
-				find_append_svg_files(container, &path, recursive)?;
+			let relative_path = path.strip_prefix(root).unwrap_or(path);
+			let is_svg = has_svg_extension(path) || has_svgz_extension(path);
+			if entry.path_is_symlink() && !options.follow_symlinks {
+				// Only links that would be processed if followed are warned about
+				if is_svg && path.is_file() && options.is_included(relative_path)
+					|| options.recursive && path.is_dir() && !options.is_excluded(relative_path) {
+					eprintln!("{}", t!("skipping-symlink", path = path.display()));
+				}
+				continue
+			}
+			if entry.file_type().is_some_and(|t| t.is_file()) && is_svg && options.is_included(relative_path) {
+				// A file reached through links is identified by its real path, so it is never processed twice
+				container.push(if options.follow_symlinks { fs::canonicalize(path)? } else { path.to_path_buf() });
 			}
 		}
 		Ok(())
 	}
 
+	fn is_symlink_loop(error: &ignore::Error) -> bool {
+		match error {
+			ignore::Error::Loop { .. } => true,
+			ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => is_symlink_loop(err),
+			_ => false,
+		}
+	}
+
 	let mut svg_files = Vec::new();
 	for temp_path in paths {
-		find_append_svg_files(&mut svg_files, &temp_path, recursive)?;
+		if temp_path.is_file() {
+			svg_files.push(temp_path.clone());
+		} else {
+			find_append_svg_files(&mut svg_files, temp_path, options)?;
+		}
 	}
 	svg_files.sort();
 	svg_files.dedup();
@@ -338,17 +850,34 @@ mod tests {
 		assert_ne!(generate_temp_dir_name(), generate_temp_dir_name());
 	}
 
+	#[test]
+	#[cfg(unix)]
+	fn test_is_temp_dir_in_use() {
+		let dir = create_temp_dir(None).unwrap();
+		assert!(is_temp_dir_in_use(&dir));
+
+		let mut child = process::Command::new("true").spawn().unwrap();
+		child.wait().unwrap();
+		fs::write(dir.join(OWNER_PID_FILE_NAME), child.id().to_string()).unwrap();
+		assert!(!is_temp_dir_in_use(&dir));
+
+		fs::remove_file(dir.join(OWNER_PID_FILE_NAME)).unwrap();
+		assert!(is_temp_dir_in_use(&dir));
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
 	#[test]
 	#[allow(non_snake_case)]
 	fn test_SvgFileGroup() {
-		let svg_file_group = SvgFileGroup::new(vec![], true);
+		let svg_file_group = SvgFileGroup::new(vec![], None, true);
 		assert!(svg_file_group.is_ok());
 		let mut svg_file_group = svg_file_group.unwrap();
 		assert!(svg_file_group.backup_dir().exists());
 		assert!(svg_file_group.delete_backups().is_ok());
 		assert!(!svg_file_group.backup_dir().exists());
 
-		let svg_file_group = SvgFileGroup::new(vec![], true);
+		let svg_file_group = SvgFileGroup::new(vec![], None, true);
 		assert!(svg_file_group.is_ok());
 		let svg_file_group = svg_file_group.unwrap();
 		let backup_dir = svg_file_group.backup_dir().to_path_buf();
@@ -356,4 +885,54 @@ mod tests {
 		drop(svg_file_group);
 		assert!(!backup_dir.exists());
 	}
+
+	#[test]
+	fn test_rolled_back_files_report() {
+		let dir = env::temp_dir().join(format!("svgc_test_rolled_back_{}", process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("icon.svg");
+		let content = "<?xml version=\"1.0\"?>\n<!-- comment -->\n<svg  xmlns=\"http://www.w3.org/2000/svg\"/>\n";
+		fs::write(&path, content).unwrap();
+
+		let mut svg_file_group = SvgFileGroup::new(vec![path.clone()], None, true).unwrap();
+		svg_file_group.apply_default_optimizations(false).unwrap();
+		assert_ne!(fs::read_to_string(&path).unwrap(), content);
+		svg_file_group.restore_files().unwrap();
+		assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+		let reports = svg_file_group.file_reports().unwrap();
+		assert_eq!(reports.len(), 1);
+		assert_eq!(reports[0].status, FileStatus::RolledBack);
+		assert_eq!(reports[0].result_size, content.len() as u64);
+		assert!(reports[0].passes.is_empty());
+
+		drop(svg_file_group);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_find_options() {
+		let options = FindOptions::new(true, false, false, false, None, &["icons/**/*.svg".to_string()], &["**/node_modules".to_string(), "icons/test/**".to_string()]).unwrap();
+		assert!(options.is_included(Path::new("icons/a.svg")));
+		assert!(options.is_included(Path::new("icons/ui/a.svg")));
+		assert!(!options.is_included(Path::new("a.svg")));
+		assert!(!options.is_included(Path::new("icons/test/a.svg")));
+		assert!(options.is_excluded(Path::new("packages/ui/node_modules")));
+	}
+
+	#[test]
+	fn test_skip_svgz_siblings() {
+		let paths = ["a.svg", "a.SVGZ", "b.SVG", "b.svgz", "c.svgz"].map(PathBuf::from).to_vec();
+		assert_eq!(skip_svgz_siblings(paths), ["a.svg", "b.SVG", "c.svgz"].map(PathBuf::from).to_vec());
+	}
+
+	#[test]
+	fn test_has_svg_extension() {
+		assert!(has_svg_extension(Path::new("icons/a.svg")));
+		assert!(has_svg_extension(Path::new("icons/a.SVG")));
+		assert!(has_svg_extension(Path::new("icons/a.Svg")));
+		assert!(has_svg_extension(Path::new(".svg")));
+		assert!(!has_svg_extension(Path::new("icons/a.svgz")));
+		assert!(!has_svg_extension(Path::new("icons/svg")));
+	}
 }
\ No newline at end of file
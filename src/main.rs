@@ -15,41 +15,111 @@
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::Duration;
 
 use clap::{Arg, ArgAction, ArgAction::SetTrue, Command};
 use lazy_static::lazy_static;
 use rust_i18n::{i18n, t};
 
+mod atomic_write;
+mod backup;
+mod cache;
+mod cancel;
+mod command;
 mod default_opt;
+mod exec;
 mod files;
+mod report;
 mod svgo;
 mod svgz;
+mod watch;
 mod i18n;
+mod preserve;
+mod savings;
 
-use files::SvgFileGroup;
+use cache::Cache;
+use exec::ExecCommand;
+use files::{FindOptions, SvgFileGroup};
 use i18n::set_rust_i18n_locale;
+use preserve::Preserve;
+use report::ReportFormat;
+use savings::MinSavings;
+use svgo::{SvgoCommand, SvgoOptions};
+use svgz::{PrecompressedFormat, SvgzCompression};
 
 i18n!();
 
+/// Exit code used in keep-going mode when some of the files could not be processed.
+const EXIT_CODE_SOME_FILES_FAILED: u8 = 2;
+
+/// Exit code used when the processing is cancelled by SIGINT or SIGTERM, as with a shell.
+const EXIT_CODE_CANCELLED: u8 = 130;
+
 fn main() -> ExitCode {
 	set_rust_i18n_locale();
 
 	lazy_static! { // need static variables for clap
-		static ref about            : Cow<'static, str> = t!("about");
-		static ref version          : Cow<'static, str> = t!("version");
-		static ref long_version     : Cow<'static, str> = t!("long-version");
-		static ref paths_help       : Cow<'static, str> = t!("paths-help");
-		static ref paths_value_name : Cow<'static, str> = t!("paths-value-name");
-	    static ref recursive_help   : Cow<'static, str> = t!("recursive-help");
-	    static ref remove_fill_help : Cow<'static, str> = t!("remove-fill-help");
-	    static ref svgo_help        : Cow<'static, str> = t!("svgo-help");
-	    static ref svgz_help        : Cow<'static, str> = t!("svgz-help");
-	    static ref no_default_help  : Cow<'static, str> = t!("no-default-help");
-	    static ref quiet_help       : Cow<'static, str> = t!("quiet-help");
-	    static ref version_help     : Cow<'static, str> = t!("version-help");
-	    static ref help_help        : Cow<'static, str> = t!("help-help");
+		static ref about                    : Cow<'static, str> = t!("about");
+		static ref version                  : Cow<'static, str> = t!("version");
+		static ref long_version             : Cow<'static, str> = t!("long-version");
+		static ref paths_help               : Cow<'static, str> = t!("paths-help");
+		static ref paths_value_name         : Cow<'static, str> = t!("paths-value-name");
+	    static ref recursive_help           : Cow<'static, str> = t!("recursive-help");
+	    static ref remove_fill_help         : Cow<'static, str> = t!("remove-fill-help");
+	    static ref svgo_help                : Cow<'static, str> = t!("svgo-help");
+	    static ref svgo_path_help           : Cow<'static, str> = t!("svgo-path-help");
+	    static ref svgo_config_help         : Cow<'static, str> = t!("svgo-config-help");
+	    static ref svgo_arg_help            : Cow<'static, str> = t!("svgo-arg-help");
+	    static ref svgo_arg_value_name      : Cow<'static, str> = t!("argument-value-name");
+	    static ref svgo_max_iterations_help : Cow<'static, str> = t!("svgo-max-iterations-help");
+	    static ref svgo_timeout_help        : Cow<'static, str> = t!("svgo-timeout-help");
+	    static ref exec_help                : Cow<'static, str> = t!("exec-help");
+	    static ref exec_value_name          : Cow<'static, str> = t!("command-value-name");
+	    static ref exec_timeout_help        : Cow<'static, str> = t!("exec-timeout-help");
+	    static ref seconds_value_name       : Cow<'static, str> = t!("seconds-value-name");
+	    static ref pipeline_help            : Cow<'static, str> = t!("pipeline-help");
+	    static ref pipeline_value_name      : Cow<'static, str> = t!("stages-value-name");
+	    static ref svgz_help                : Cow<'static, str> = t!("svgz-help");
+	    static ref keep_svg_help            : Cow<'static, str> = t!("keep-svg-help");
+	    static ref svgz_level_help          : Cow<'static, str> = t!("svgz-level-help");
+	    static ref level_value_name         : Cow<'static, str> = t!("level-value-name");
+	    static ref zopfli_help              : Cow<'static, str> = t!("zopfli-help");
+	    static ref precompress_help         : Cow<'static, str> = t!("precompress-help");
+	    static ref formats_value_name       : Cow<'static, str> = t!("formats-value-name");
+	    static ref no_default_help          : Cow<'static, str> = t!("no-default-help");
+	    static ref quiet_help               : Cow<'static, str> = t!("quiet-help");
+	    static ref keep_going_help          : Cow<'static, str> = t!("keep-going-help");
+	    static ref preserve_help            : Cow<'static, str> = t!("preserve-help");
+	    static ref preserve_value_name      : Cow<'static, str> = t!("attributes-value-name");
+	    static ref backup_help              : Cow<'static, str> = t!("backup-help");
+	    static ref restore_about            : Cow<'static, str> = t!("restore-about");
+	    static ref backup_run_help          : Cow<'static, str> = t!("backup-run-help");
+	    static ref temp_dir_help            : Cow<'static, str> = t!("temp-dir-help");
+	    static ref clean_backups_about      : Cow<'static, str> = t!("clean-backups-about");
+	    static ref dry_run_help             : Cow<'static, str> = t!("dry-run-help");
+	    static ref no_ignore_help           : Cow<'static, str> = t!("no-ignore-help");
+	    static ref follow_symlinks_help     : Cow<'static, str> = t!("follow-symlinks-help");
+	    static ref hidden_help              : Cow<'static, str> = t!("hidden-help");
+	    static ref max_depth_help           : Cow<'static, str> = t!("max-depth-help");
+	    static ref include_help             : Cow<'static, str> = t!("include-help");
+	    static ref exclude_help             : Cow<'static, str> = t!("exclude-help");
+	    static ref pattern_value_name       : Cow<'static, str> = t!("pattern-value-name");
+	    static ref path_value_name          : Cow<'static, str> = t!("path-value-name");
+	    static ref number_value_name        : Cow<'static, str> = t!("number-value-name");
+	    static ref directory_value_name     : Cow<'static, str> = t!("directory-value-name");
+	    static ref cache_help               : Cow<'static, str> = t!("cache-help");
+	    static ref min_savings_help         : Cow<'static, str> = t!("min-savings-help");
+	    static ref min_savings_value_name   : Cow<'static, str> = t!("min-savings-value-name");
+	    static ref watch_help               : Cow<'static, str> = t!("watch-help");
+	    static ref jobs_help                : Cow<'static, str> = t!("jobs-help");
+	    static ref report_format_help       : Cow<'static, str> = t!("report-format-help");
+	    static ref report_format_value_name : Cow<'static, str> = t!("format-value-name");
+	    static ref report_file_help         : Cow<'static, str> = t!("report-file-help");
+	    static ref version_help             : Cow<'static, str> = t!("version-help");
+	    static ref help_help                : Cow<'static, str> = t!("help-help");
 	}
 
 	let matches = Command::new("svgc")
@@ -63,15 +133,128 @@ fn main() -> ExitCode {
 		.arg(Arg::new("recursive")  .short('r').long("recursive")  .help(&recursive_help[..])  .action(SetTrue))
 		.arg(Arg::new("remove-fill").short('f').long("remove-fill").help(&remove_fill_help[..]).action(SetTrue))
 		.arg(Arg::new("svgo")       .short('o').long("svgo")       .help(&svgo_help[..])       .action(SetTrue))
+		.arg(Arg::new("svgo-path").long("svgo-path").help(&svgo_path_help[..])
+			.value_name(&path_value_name[..])
+			.env("SVGC_SVGO"))
+		.arg(Arg::new("svgo-config").long("svgo-config").help(&svgo_config_help[..])
+			.value_name(&path_value_name[..])
+			.requires("svgo"))
+		.arg(Arg::new("svgo-arg").long("svgo-arg").help(&svgo_arg_help[..])
+			.value_name(&svgo_arg_value_name[..])
+			.allow_hyphen_values(true)
+			.action(ArgAction::Append)
+			.requires("svgo"))
+		.arg(Arg::new("svgo-max-iterations").long("svgo-max-iterations").help(&svgo_max_iterations_help[..])
+			.value_name(&number_value_name[..])
+			.value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+			.default_value("10")
+			.requires("svgo"))
+		.arg(Arg::new("svgo-timeout").long("svgo-timeout").help(&svgo_timeout_help[..])
+			.value_name(&seconds_value_name[..])
+			.value_parser(clap::value_parser!(u64).range(1..))
+			.requires("svgo"))
+		.arg(Arg::new("exec").long("exec").help(&exec_help[..])
+			.value_name(&exec_value_name[..])
+			.value_parser(|template: &str| ExecCommand::parse(template))
+			.action(ArgAction::Append))
+		.arg(Arg::new("exec-timeout").long("exec-timeout").help(&exec_timeout_help[..])
+			.value_name(&seconds_value_name[..])
+			.value_parser(clap::value_parser!(u64).range(1..))
+			.requires("exec"))
+		.arg(Arg::new("pipeline").long("pipeline").help(&pipeline_help[..])
+			.value_name(&pipeline_value_name[..])
+			.value_parser(Stage::NAMES)
+			.value_delimiter(',')
+			.default_value("default,svgo,exec"))
 		.arg(Arg::new("svgz")       .short('z').long("svgz")       .help(&svgz_help[..])       .action(SetTrue))
+		.arg(Arg::new("keep-svg").long("keep-svg").help(&keep_svg_help[..]).action(SetTrue).requires("svgz"))
+		.arg(Arg::new("svgz-level").long("svgz-level").help(&svgz_level_help[..])
+			.value_name(&level_value_name[..])
+			.value_parser(clap::value_parser!(u32).range(0..=9))
+			.conflicts_with("zopfli"))
+		.arg(Arg::new("zopfli").long("zopfli").help(&zopfli_help[..]).action(SetTrue))
+		.arg(Arg::new("precompress").long("precompress").help(&precompress_help[..])
+			.value_name(&formats_value_name[..])
+			.value_parser(PrecompressedFormat::NAMES)
+			.value_delimiter(','))
 		.arg(Arg::new("no-default") .short('n').long("no-default") .help(&no_default_help[..]) .action(SetTrue))
-		.arg(Arg::new("quiet")      .short('q').long("quiet")      .help(&quiet_help[..])      .action(SetTrue))
+		.arg(Arg::new("quiet")      .short('q').long("quiet")      .help(&quiet_help[..])      .action(SetTrue).global(true))
+		.arg(Arg::new("keep-going") .short('k').long("keep-going") .help(&keep_going_help[..]) .action(SetTrue))
+		.arg(Arg::new("preserve").long("preserve").help(&preserve_help[..])
+			.value_name(&preserve_value_name[..])
+			.value_parser(Preserve::NAMES)
+			.value_delimiter(',')
+			.default_value("mode,ownership"))
+		.arg(Arg::new("min-savings").long("min-savings").help(&min_savings_help[..])
+			.value_name(&min_savings_value_name[..])
+			.value_parser(|value: &str| value.parse::<MinSavings>()))
+		.arg(Arg::new("no-ignore").long("no-ignore").help(&no_ignore_help[..]).action(SetTrue))
+		.arg(Arg::new("follow-symlinks").long("follow-symlinks").help(&follow_symlinks_help[..]).action(SetTrue))
+		.arg(Arg::new("hidden").long("hidden").help(&hidden_help[..]).action(SetTrue))
+		.arg(Arg::new("max-depth").long("max-depth").help(&max_depth_help[..])
+			.value_name(&number_value_name[..])
+			.value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+			.requires("recursive"))
+		.arg(Arg::new("include").long("include").help(&include_help[..])
+			.value_name(&pattern_value_name[..])
+			.action(ArgAction::Append))
+		.arg(Arg::new("exclude").long("exclude").help(&exclude_help[..])
+			.value_name(&pattern_value_name[..])
+			.action(ArgAction::Append))
+		.arg(Arg::new("backup").long("backup").help(&backup_help[..])
+			.value_name(&directory_value_name[..]))
+		.arg(Arg::new("temp-dir").long("temp-dir").help(&temp_dir_help[..])
+			.value_name(&directory_value_name[..])
+			.env("SVGC_TEMP_DIR")
+			.global(true))
+		.arg(Arg::new("cache").long("cache").help(&cache_help[..])
+			.value_name(&path_value_name[..])
+			.num_args(0..=1)
+			.require_equals(true)
+			.default_missing_value(cache::DEFAULT_CACHE_FILE_NAME))
+		.arg(Arg::new("watch")      .short('w').long("watch")      .help(&watch_help[..])      .action(SetTrue))
+		.arg(Arg::new("jobs")       .short('j').long("jobs")       .help(&jobs_help[..])
+			.value_name(&number_value_name[..])
+			.value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)))
+		.arg(Arg::new("report-format").long("report-format").help(&report_format_help[..])
+			.value_name(&report_format_value_name[..])
+			.value_parser(ReportFormat::NAMES))
+		.arg(Arg::new("report-file").long("report-file").help(&report_file_help[..])
+			.value_name(&path_value_name[..]))
 		.disable_version_flag(true)
 		.arg(Arg::new("version")    .short('v').long("version")    .help(&version_help[..])    .action(ArgAction::Version))
 		.disable_help_flag(true)
 		.arg(Arg::new("help")       .short('h').long("help")       .help(&help_help[..])       .action(ArgAction::Help))
+		.subcommand(Command::new("restore")
+			.about(&restore_about[..])
+			.arg(Arg::new("backup-run").help(&backup_run_help[..])
+				.value_name(&directory_value_name[..])
+				.required(true))
+			.disable_help_flag(true)
+			.arg(Arg::new("help")   .short('h').long("help")       .help(&help_help[..])       .action(ArgAction::Help)))
+		.subcommand(Command::new("clean-backups")
+			.about(&clean_backups_about[..])
+			.arg(Arg::new("dry-run").long("dry-run").help(&dry_run_help[..]).action(SetTrue))
+			.disable_help_flag(true)
+			.arg(Arg::new("help")   .short('h').long("help")       .help(&help_help[..])       .action(ArgAction::Help)))
+		.disable_help_subcommand(true)
+		.subcommand_negates_reqs(true)
+		.args_conflicts_with_subcommands(true)
 		.get_matches();
 
+	let temp_dir = matches.get_one::<String>("temp-dir").map(PathBuf::from);
+
+	match matches.subcommand() {
+		Some(("restore", restore_matches)) => {
+			let backup_run = Path::new(restore_matches.get_one::<String>("backup-run").unwrap());
+			return restore_backup_run(backup_run, restore_matches.get_flag("quiet"))
+		}
+		Some(("clean-backups", clean_matches)) => {
+			return clean_backups(temp_dir.as_deref(), clean_matches.get_flag("dry-run"), clean_matches.get_flag("quiet"))
+		}
+		_ => {}
+	}
+
 	let mut paths: Vec<PathBuf> =
 		matches
 			.get_many::<String>("paths")
@@ -102,10 +285,52 @@ fn main() -> ExitCode {
 	let remove_fill = matches.get_flag("remove-fill");
 	let use_svgo = matches.get_flag("svgo");
 	let compress_svgz = matches.get_flag("svgz");
+	let keep_svg = matches.get_flag("keep-svg");
+	let precompress: Vec<PrecompressedFormat> = matches.get_many::<String>("precompress").unwrap_or_default()
+		.filter_map(|name| PrecompressedFormat::from_name(name))
+		.collect();
+	let svgz_compression = if matches.get_flag("zopfli") {
+		SvgzCompression::Zopfli
+	} else {
+		matches.get_one::<u32>("svgz-level").map_or_else(SvgzCompression::default, |&level| SvgzCompression::Level(level))
+	};
+	let exec_commands: Vec<ExecCommand> = matches.get_many::<ExecCommand>("exec").unwrap_or_default().cloned().collect();
+	let exec_timeout = matches.get_one::<u64>("exec-timeout").map(|&seconds| Duration::from_secs(seconds));
+	let pipeline: Vec<Stage> = matches.get_many::<String>("pipeline").unwrap().filter_map(|name| Stage::from_name(name)).collect();
+	let watch = matches.get_flag("watch");
 	let no_default = matches.get_flag("no-default");
 	let quiet = matches.get_flag("quiet");
+	let keep_going = matches.get_flag("keep-going");
+	let min_savings = matches.get_one::<MinSavings>("min-savings").copied();
+	let preserve = Preserve::from_names(matches.get_many::<String>("preserve").unwrap().map(String::as_str));
+	let backup_dir = matches.get_one::<String>("backup").map(PathBuf::from);
+	let cache_file = matches.get_one::<String>("cache").map(PathBuf::from);
+	let jobs = matches.get_one::<usize>("jobs").copied().unwrap_or(0);
+	let report_file = matches.get_one::<String>("report-file").map(PathBuf::from);
+	let report_format = matches.get_one::<String>("report-format")
+		.and_then(|name| ReportFormat::from_name(name))
+		.or_else(|| report_file.as_deref().map(ReportFormat::from_path));
+
+	// Only the .svg file kept next to the .svgz one can be precompressed
+	if compress_svgz && !keep_svg && !precompress.is_empty() {
+		eprintln!("{}", t!("error-precompress-requires-keep-svg"));
+		return ExitCode::FAILURE
+	}
 
-	if no_default && !use_svgo && !compress_svgz {
+	// A stage enabled by its option is not left out of the pipeline silently
+	let left_out_stage = if use_svgo && !pipeline.contains(&Stage::Svgo) {
+		Some("svgo")
+	} else if !exec_commands.is_empty() && !pipeline.contains(&Stage::Exec) {
+		Some("exec")
+	} else {
+		None
+	};
+	if let Some(stage) = left_out_stage {
+		eprintln!("{}", t!("error-stage-not-in-pipeline", stage = stage));
+		return ExitCode::FAILURE
+	}
+
+	if no_default && !use_svgo && !compress_svgz && exec_commands.is_empty() && precompress.is_empty() {
 		if !quiet {
 			println!("{}", t!("no-action-specified-files-not-modified"));
 			println!("{}", t!("type-svg-help-for-more-information"));
@@ -113,11 +338,26 @@ fn main() -> ExitCode {
 		return ExitCode::SUCCESS
 	}
 
-	let svgo_path = if use_svgo {
-		match which::which("svgo") {
-			Ok(path) => Some(path),
-			Err(_) => {
-				eprintln!("{}", t!("error-svgo"));
+	// 0 lets rayon use one thread per logical CPU
+	if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global() {
+		eprintln!("{}", t!("error-creating-thread-pool", error = e));
+	}
+
+	let svgo = if use_svgo {
+		let svgo_path = matches.get_one::<String>("svgo-path").map(Path::new);
+		match SvgoCommand::find(svgo_path) {
+			Some(command) => Some(SvgoOptions {
+				command,
+				config: matches.get_one::<String>("svgo-config").map(PathBuf::from).or_else(svgo::find_config),
+				args: matches.get_many::<String>("svgo-arg").unwrap_or_default().cloned().collect(),
+				max_iterations: *matches.get_one::<usize>("svgo-max-iterations").unwrap(),
+				timeout: matches.get_one::<u64>("svgo-timeout").map(|&seconds| Duration::from_secs(seconds)),
+			}),
+			None => {
+				match svgo_path {
+					Some(path) => eprintln!("{}", t!("error-svgo-path", path = path.display())),
+					None => eprintln!("{}", t!("error-svgo")),
+				}
 				if !quiet { println!("{}", t!("your-files-were-not-modified")); }
 				return ExitCode::FAILURE
 			}
@@ -130,7 +370,28 @@ fn main() -> ExitCode {
 		return ExitCode::SUCCESS
 	}
 
-	let svg_files = match files::find_svg_files(&paths, recursive) {
+	let find_options = {
+		let patterns = |id| matches.get_many::<String>(id).unwrap_or_default().cloned().collect::<Vec<_>>();
+		match FindOptions::new(
+			recursive,
+			!matches.get_flag("no-ignore"),
+			matches.get_flag("follow-symlinks"),
+			matches.get_flag("hidden"),
+			matches.get_one::<usize>("max-depth").copied(),
+			&patterns("include"),
+			&patterns("exclude"),
+		) {
+			Ok(options) => options,
+			Err(e) => {
+				eprintln!("{}", t!("error-invalid-pattern", error = e));
+				if !quiet { println!("{}", t!("your-files-were-not-modified")); }
+				return ExitCode::FAILURE
+			}
+		}
+	};
+
+	let svg_files = match files::find_svg_files(&paths, &find_options) {
+		Ok(files) if compress_svgz => files::skip_svgz_siblings(files),
 		Ok(files) => files,
 		Err(e) => {
 			eprintln!("{}", t!("error-finding-svg-files", error = e));
@@ -139,7 +400,134 @@ fn main() -> ExitCode {
 		}
 	};
 
-	let mut svg_file_group = match SvgFileGroup::new(svg_files, false) {
+	// Everything that affects the produced files
+	let mut cache_options = format!("remove-fill={remove_fill} default={} svgo={use_svgo} svgz={compress_svgz} svgz-compression={svgz_compression:?} keep-svg={keep_svg} precompress={precompress:?} min-savings={}", !no_default, min_savings.map(|min_savings| min_savings.to_string()).unwrap_or_default());
+	cache_options += &format!(" pipeline={pipeline:?} exec={:?}", exec_commands.iter().map(ExecCommand::template).collect::<Vec<_>>());
+	if let Some(svgo) = &svgo {
+		// The content of the configuration matters rather than its path
+		let config_hash = svgo.config.as_deref().map(|config| backup::sha256_file(config).unwrap_or_default());
+		cache_options += &format!(" svgo-config={config_hash:?} svgo-args={:?} svgo-max-iterations={} svgo-version={}", svgo.args, svgo.max_iterations, svgo.command.version);
+	}
+	let mut cache = cache_file.and_then(|path| {
+		Cache::load(&path, &cache_options)
+			.inspect_err(|e| eprintln!("{}", t!("error-loading-cache", path = path.display(), error = e)))
+			.ok()
+	});
+
+	let options = ProcessOptions {
+		remove_fill,
+		no_default,
+		svgo,
+		exec_commands,
+		exec_timeout,
+		pipeline,
+		compress_svgz,
+		svgz_compression,
+		keep_svg,
+		precompress,
+		quiet,
+		keep_going,
+		min_savings,
+		preserve,
+		backup_dir,
+		temp_dir,
+		report_format,
+		report_file,
+	};
+
+	if let Err(e) = cancel::install_handler() {
+		eprintln!("{}", t!("error-installing-signal-handler", error = e));
+	}
+
+	if watch {
+		// Files produced by the program itself are recognized by their hashes and not processed again
+		let mut cache = cache.unwrap_or_else(|| Cache::in_memory(&cache_options));
+		// Files restored after a failure are not saved in the cache, so they are ignored separately
+		let mut restored = Cache::in_memory(&cache_options);
+		if !quiet { println!("{}", t!("watching-for-changes")); }
+		let result = watch::watch(&paths, &find_options, |svg_files| {
+			let svg_files = if compress_svgz { files::skip_svgz_siblings(svg_files) } else { svg_files };
+			let (svg_files, _) = cache.partition(svg_files);
+			let (svg_files, _) = restored.partition(svg_files);
+			if !svg_files.is_empty() {
+				let hashes = cache::hash_files(&svg_files);
+				process_files(svg_files, Vec::new(), &options, Some(&mut cache));
+				restored.insert_unchanged(&hashes);
+			}
+		});
+		if let Err(e) = result {
+			eprintln!("{}", t!("error-watching-for-changes", error = e));
+			return ExitCode::FAILURE
+		}
+		return ExitCode::from(EXIT_CODE_CANCELLED)
+	}
+
+	let (svg_files, unchanged_files) = match &cache {
+		Some(cache) => cache.partition(svg_files),
+		None => (svg_files, Vec::new()),
+	};
+
+	process_files(svg_files, unchanged_files, &options, cache.as_mut())
+}
+
+/// Optimization stages, whose order can be changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+	Default,
+	Svgo,
+	Exec,
+}
+
+impl Stage {
+	const NAMES: [&'static str; 3] = ["default", "svgo", "exec"];
+
+	fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"default" => Some(Self::Default),
+			"svgo" => Some(Self::Svgo),
+			"exec" => Some(Self::Exec),
+			_ => None,
+		}
+	}
+}
+
+/// Options of processing the found files.
+struct ProcessOptions {
+	remove_fill: bool,
+	no_default: bool,
+	svgo: Option<SvgoOptions>,
+	exec_commands: Vec<ExecCommand>,
+	exec_timeout: Option<Duration>,
+	pipeline: Vec<Stage>,
+	compress_svgz: bool,
+	svgz_compression: SvgzCompression,
+	keep_svg: bool,
+	precompress: Vec<PrecompressedFormat>,
+	quiet: bool,
+	keep_going: bool,
+	min_savings: Option<MinSavings>,
+	preserve: Preserve,
+	backup_dir: Option<PathBuf>,
+	temp_dir: Option<PathBuf>,
+	report_format: Option<ReportFormat>,
+	report_file: Option<PathBuf>,
+}
+
+impl ProcessOptions {
+	/// A report printed to stdout must stay parsable, so regular messages go elsewhere.
+	fn reports_to_stdout(&self) -> bool {
+		self.report_format.is_some() && self.report_file.is_none()
+	}
+}
+
+fn process_files(svg_files: Vec<PathBuf>, unchanged_files: Vec<(PathBuf, u64)>, options: &ProcessOptions, cache: Option<&mut Cache>) -> ExitCode {
+	let quiet = options.quiet;
+
+	let svg_file_group = match &options.backup_dir {
+		Some(dir) => SvgFileGroup::new_persistent(svg_files, dir),
+		None => SvgFileGroup::new(svg_files, options.temp_dir.as_deref(), false),
+	};
+	let mut svg_file_group = match svg_file_group {
 		Ok(group) => group,
 		Err(e) => {
 			eprintln!("{}", t!("error-creating-temporary-backup-storage", error = e));
@@ -148,50 +536,175 @@ fn main() -> ExitCode {
 		}
 	};
 
-	if !no_default {
-		if let Err(e) = svg_file_group.apply_default_optimizations(remove_fill) {
-			eprintln!("{}", t!("error-optimizing-files", error = e));
-			try_to_restore(&mut svg_file_group, quiet);
-			return ExitCode::FAILURE
-		}
+	if options.keep_going {
+		svg_file_group.enable_keep_going();
 	}
+	svg_file_group.add_unchanged_files(unchanged_files);
 
-	if use_svgo && svgo_path != None {
-		if let Err(e) = svg_file_group.apply_svgo(&svgo_path.unwrap()) {
-			eprintln!("{}", t!("error-optimizing-files-with-svgo", error = e));
-			try_to_restore(&mut svg_file_group, quiet);
-			return ExitCode::FAILURE
-		};
+	let result = (|| {
+		svg_file_group.decompress_inputs()
+			.map_err(|e| t!("error-decompressing-files", error = e))?;
+		for stage in &options.pipeline {
+			match stage {
+				Stage::Default if !options.no_default => {
+					svg_file_group.apply_default_optimizations(options.remove_fill)
+						.map_err(|e| t!("error-optimizing-files", error = e))?;
+				}
+				Stage::Svgo => if let Some(svgo) = &options.svgo {
+					svg_file_group.apply_svgo(svgo)
+						.map_err(|e| t!("error-optimizing-files-with-svgo", error = e))?;
+				}
+				Stage::Exec => for command in &options.exec_commands {
+					svg_file_group.apply_exec(command, options.exec_timeout)
+						.map_err(|e| t!("error-optimizing-files-with-command", command = command.name(), error = e))?;
+				}
+				_ => {}
+			}
+		}
+		if options.compress_svgz {
+			svg_file_group.compress(options.svgz_compression, options.keep_svg)
+				.map_err(|e| t!("error-compressing-files", error = e))?;
+		}
+		svg_file_group.recompress_inputs(options.svgz_compression)
+			.map_err(|e| t!("error-compressing-files", error = e))?;
+		if !options.precompress.is_empty() {
+			svg_file_group.precompress(&options.precompress, options.svgz_compression)
+				.map_err(|e| t!("error-compressing-files", error = e))?;
+		}
+		if let Some(min_savings) = options.min_savings {
+			svg_file_group.skip_insufficient_savings(min_savings)
+				.map_err(|e| t!("error-restoring-files-without-savings", error = e))?;
+		}
+		if options.preserve != Preserve::default() {
+			svg_file_group.preserve_attributes(options.preserve)
+				.map_err(|e| t!("error-preserving-file-attributes", error = e))?;
+		}
+		Ok(())
+	})();
+
+	if let Err(message) = &result {
+		eprintln!("{message}");
+		try_to_restore(&mut svg_file_group, quiet, options.reports_to_stdout());
 	}
 
-	if compress_svgz {
-		if let Err(e) = svg_file_group.compress() {
-			eprintln!("{}", t!("error-compressing-files", error = e));
-			try_to_restore(&mut svg_file_group, quiet);
-			return ExitCode::FAILURE
+	if let Some(format) = options.report_format {
+		if let Err(e) = write_report(&mut svg_file_group, format, options.report_file.as_deref(), result.as_ref().err()) {
+			eprintln!("{}", t!("error-writing-report", error = e));
 		}
 	}
 
-	if !quiet {
+	if result.is_err() {
+		return if cancel::is_cancelled() { ExitCode::from(EXIT_CODE_CANCELLED) } else { ExitCode::FAILURE }
+	}
+
+	// The human-readable summary gives way to a report printed to stdout
+	if !quiet && !options.reports_to_stdout() {
 		if let Err(e) = svg_file_group.print_summary() {
 			eprintln!("{}", t!("error-printing-summary", error = e));
 		}
+		if let Some(svgo) = &options.svgo {
+			println!("{}", t!("svgo-version-used", version = svgo.command.version));
+		}
+	}
+
+	if let Some(cache) = cache {
+		for hash in svg_file_group.result_hashes() {
+			match hash {
+				Ok(hash) => cache.insert(&hash),
+				Err(e) => eprintln!("{}", t!("error-updating-cache", error = e)),
+			}
+		}
+		if let Err(e) = cache.save() {
+			eprintln!("{}", t!("error-updating-cache", error = e));
+		}
+	}
+
+	let mut has_failures = false;
+	for (path, error) in svg_file_group.failed_files() {
+		eprintln!("{}", t!("error-processing-file", path = path.display(), error = error));
+		has_failures = true;
+	}
+
+	if svg_file_group.is_persistent() {
+		if let Err(e) = svg_file_group.update_manifest() {
+			eprintln!("{}", t!("error-writing-backup-manifest", error = e));
+		}
+		if !quiet {
+			let message = t!("backups-saved-in-dir", dir = svg_file_group.backup_dir().display());
+			if options.reports_to_stdout() { eprintln!("{message}") } else { println!("{message}") }
+		}
+	} else {
+		svg_file_group.enable_auto_delete_backups();
 	}
 
-	svg_file_group.enable_auto_delete_backups();
+	if has_failures {
+		ExitCode::from(EXIT_CODE_SOME_FILES_FAILED)
+	} else {
+		ExitCode::SUCCESS
+	}
+}
 
-	ExitCode::SUCCESS
+fn write_report(svg_file_group: &mut SvgFileGroup, format: ReportFormat, report_file: Option<&Path>, error: Option<&Cow<str>>) -> io::Result<()> {
+	let files = svg_file_group.file_reports()?;
+	let error = error.map(|e| e.as_ref());
+	match report_file {
+		Some(path) => {
+			let mut writer = io::BufWriter::new(fs::File::create(path)?);
+			report::write_report(&mut writer, format, &files, error)?;
+			io::Write::flush(&mut writer)
+		}
+		None => report::write_report(&mut io::stdout().lock(), format, &files, error),
+	}
 }
 
-fn try_to_restore(temp_storage: &mut SvgFileGroup, quiet: bool) {
+fn try_to_restore(temp_storage: &mut SvgFileGroup, quiet: bool, reports_to_stdout: bool) {
 	if let Err(e) = temp_storage.restore_files() {
 		temp_storage.disable_auto_delete_backups();
 		eprintln!("{}", t!("error-restoring-files", error = e, dir = temp_storage.backup_dir().display()));
 		return
-	} else {
+	} else if !temp_storage.is_persistent() {
 		temp_storage.enable_auto_delete_backups();
 	}
 	if !quiet {
-		println!("{}", t!("files-restored"));
+		let message = t!("files-restored");
+		if reports_to_stdout { eprintln!("{message}") } else { println!("{message}") }
+	}
+}
+
+fn restore_backup_run(backup_run: &Path, quiet: bool) -> ExitCode {
+	match backup::restore_backup_run(backup_run) {
+		Ok(restored) => {
+			if !quiet {
+				for path in restored {
+					println!("{}", t!("file-restored", path = path.display()));
+				}
+				println!("{}", t!("files-restored"));
+			}
+			ExitCode::SUCCESS
+		}
+		Err(e) => {
+			eprintln!("{}", t!("error-restoring-backup-run", dir = backup_run.display(), error = e));
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn clean_backups(temp_dir: Option<&Path>, dry_run: bool, quiet: bool) -> ExitCode {
+	let leftover_dirs = files::find_leftover_temp_dirs(temp_dir);
+	if leftover_dirs.is_empty() {
+		if !quiet { println!("{}", t!("no-leftover-backups-found")); }
+		return ExitCode::SUCCESS
+	}
+	let mut exit_code = ExitCode::SUCCESS;
+	for dir in leftover_dirs {
+		if dry_run {
+			println!("{}", dir.display());
+		} else if let Err(e) = fs::remove_dir_all(&dir) {
+			eprintln!("{}", t!("failed-to-delete-backups-dir", dir = dir.display(), error = e));
+			exit_code = ExitCode::FAILURE;
+		} else if !quiet {
+			println!("{}", t!("backups-dir-deleted", dir = dir.display()));
+		}
 	}
+	exit_code
 }
\ No newline at end of file
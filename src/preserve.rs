@@ -0,0 +1,91 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use filetime::FileTime;
+
+/// File attributes carried over from the original file to the result file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Preserve {
+	pub mode: bool,
+	pub timestamps: bool,
+	pub ownership: bool,
+}
+
+impl Preserve {
+	pub const NAMES: [&'static str; 5] = ["mode", "timestamps", "ownership", "all", "none"];
+
+	/// The given attributes are added to the mode and ownership, which are preserved unless `none` is given.
+	pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+		let mut preserve = Self { mode: true, timestamps: false, ownership: true };
+		for name in names {
+			match name {
+				"mode" => preserve.mode = true,
+				"timestamps" => preserve.timestamps = true,
+				"ownership" => preserve.ownership = true,
+				"all" => preserve = Self { mode: true, timestamps: true, ownership: true },
+				"none" => preserve = Self::default(),
+				_ => {}
+			}
+		}
+		preserve
+	}
+
+	/// Attributes restored together with the content of a file put back from its backup.
+	pub fn restore() -> Self {
+		Self { mode: true, timestamps: true, ownership: true }
+	}
+
+	/// Ownership is copied where permitted only, like `cp -p` does, as only the superuser
+	/// can give files to other users.
+	pub fn apply(&self, original: &fs::Metadata, path: &Path) -> io::Result<()> {
+		if self.timestamps {
+			filetime::set_file_times(path, FileTime::from_last_access_time(original), FileTime::from_last_modification_time(original))?;
+		}
+		#[cfg(unix)]
+		if self.ownership {
+			use std::os::unix::fs::MetadataExt;
+			let current = fs::metadata(path)?;
+			if (current.uid(), current.gid()) != (original.uid(), original.gid()) {
+				match std::os::unix::fs::chown(path, Some(original.uid()), Some(original.gid())) {
+					Err(e) if e.kind() != io::ErrorKind::PermissionDenied => return Err(e),
+					_ => {}
+				}
+			}
+		}
+		// Permissions go last, as they may make the file read-only
+		if self.mode {
+			fs::set_permissions(path, original.permissions())?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_from_names() {
+		assert_eq!(Preserve::from_names(["none"]), Preserve::default());
+		assert_eq!(Preserve::from_names(["none", "timestamps"]), Preserve { mode: false, timestamps: true, ownership: false });
+		assert_eq!(Preserve::from_names(["timestamps"]), Preserve { mode: true, timestamps: true, ownership: true });
+		assert_eq!(Preserve::from_names(["all"]), Preserve { mode: true, timestamps: true, ownership: true });
+	}
+}
\ No newline at end of file
@@ -0,0 +1,210 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::json;
+
+/// Machine-readable formats of the run report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+	Json,
+	Csv,
+	Markdown,
+}
+
+impl ReportFormat {
+	pub const NAMES: [&'static str; 3] = ["json", "csv", "markdown"];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"json" => Some(Self::Json),
+			"csv" => Some(Self::Csv),
+			"markdown" => Some(Self::Markdown),
+			_ => None,
+		}
+	}
+
+	/// Guesses the format from the extension of the report file, falling back to JSON.
+	pub fn from_path(path: &Path) -> Self {
+		match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+			Some("csv") => Self::Csv,
+			Some("md" | "markdown") => Self::Markdown,
+			_ => Self::Json,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatus {
+	Optimized,
+	/// The file was already optimized by a previous run with the same options.
+	Unchanged,
+	/// The result did not save enough, so the original file was kept.
+	Skipped,
+	Failed,
+	/// Processing of other files failed, so the file was restored.
+	RolledBack,
+}
+
+impl FileStatus {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Optimized => "optimized",
+			Self::Unchanged => "unchanged",
+			Self::Skipped => "skipped",
+			Self::Failed => "failed",
+			Self::RolledBack => "rolled-back",
+		}
+	}
+}
+
+/// Outcome of processing a single file, as it appears in the report.
+pub struct FileReport<'a> {
+	pub status: FileStatus,
+	pub original_path: &'a Path,
+	pub result_path: &'a Path,
+	pub original_size: u64,
+	pub result_size: u64,
+	pub passes: &'a [&'static str],
+	pub error: Option<&'a str>,
+}
+
+impl FileReport<'_> {
+	pub fn savings(&self) -> i64 {
+		self.original_size as i64 - self.result_size as i64
+	}
+
+	pub fn savings_percent(&self) -> f64 {
+		if self.original_size == 0 {
+			0.0
+		} else {
+			self.savings() as f64 / self.original_size as f64 * 100.0
+		}
+	}
+}
+
+pub fn write_report(writer: &mut impl Write, format: ReportFormat, files: &[FileReport], error: Option<&str>) -> io::Result<()> {
+	match format {
+		ReportFormat::Json => write_json(writer, files, error),
+		ReportFormat::Csv => write_csv(writer, files),
+		ReportFormat::Markdown => write_markdown(writer, files, error),
+	}
+}
+
+fn totals(files: &[FileReport]) -> (u64, u64) {
+	files.iter().fold((0, 0), |(before, after), file| (before + file.original_size, after + file.result_size))
+}
+
+fn write_json(writer: &mut impl Write, files: &[FileReport], error: Option<&str>) -> io::Result<()> {
+	let (total_before, total_after) = totals(files);
+	let report = json!({
+		"version": env!("CARGO_PKG_VERSION"),
+		"files": files.iter().map(|file| json!({
+			"status": file.status.as_str(),
+			"path": file.original_path,
+			"result_path": file.result_path,
+			"original_size": file.original_size,
+			"result_size": file.result_size,
+			"savings": file.savings(),
+			"savings_percent": file.savings_percent(),
+			"passes": file.passes,
+			"error": file.error,
+		})).collect::<Vec<_>>(),
+		"total": {
+			"original_size": total_before,
+			"result_size": total_after,
+			"savings": total_before as i64 - total_after as i64,
+		},
+		"error": error,
+	});
+	serde_json::to_writer_pretty(&mut *writer, &report)?;
+	writeln!(writer)
+}
+
+fn csv_field(value: &str) -> String {
+	if value.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_string()
+	}
+}
+
+fn write_csv(writer: &mut impl Write, files: &[FileReport]) -> io::Result<()> {
+	writeln!(writer, "status,path,result_path,original_size,result_size,savings,savings_percent,passes,error")?;
+	for file in files {
+		writeln!(writer, "{},{},{},{},{},{},{:.2},{},{}",
+			file.status.as_str(),
+			csv_field(&file.original_path.to_string_lossy()),
+			csv_field(&file.result_path.to_string_lossy()),
+			file.original_size,
+			file.result_size,
+			file.savings(),
+			file.savings_percent(),
+			csv_field(&file.passes.join(";")),
+			csv_field(file.error.unwrap_or_default()),
+		)?;
+	}
+	Ok(())
+}
+
+fn markdown_cell(value: &str) -> String {
+	value.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+fn write_markdown(writer: &mut impl Write, files: &[FileReport], error: Option<&str>) -> io::Result<()> {
+	writeln!(writer, "| File | Result | Status | Original size | Result size | Savings | Passes | Error |")?;
+	writeln!(writer, "|---|---|---|---:|---:|---:|---|---|")?;
+	for file in files {
+		writeln!(writer, "| {} | {} | {} | {} | {} | {} ({:.2}%) | {} | {} |",
+			markdown_cell(&file.original_path.to_string_lossy()),
+			markdown_cell(&file.result_path.to_string_lossy()),
+			file.status.as_str(),
+			file.original_size,
+			file.result_size,
+			file.savings(),
+			file.savings_percent(),
+			file.passes.join(", "),
+			markdown_cell(file.error.unwrap_or_default()),
+		)?;
+	}
+	let (total_before, total_after) = totals(files);
+	writeln!(writer, "| **Total** | | | {total_before} | {total_after} | {} | | |", total_before as i64 - total_after as i64)?;
+	if let Some(error) = error {
+		writeln!(writer, "\n**Error:** {}", markdown_cell(error))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_csv_field() {
+		assert_eq!(csv_field("icon.svg"), "icon.svg");
+		assert_eq!(csv_field("a,b.svg"), "\"a,b.svg\"");
+		assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+	}
+
+	#[test]
+	fn test_report_format_from_path() {
+		assert_eq!(ReportFormat::from_path(Path::new("report.csv")), ReportFormat::Csv);
+		assert_eq!(ReportFormat::from_path(Path::new("report.MD")), ReportFormat::Markdown);
+		assert_eq!(ReportFormat::from_path(Path::new("report")), ReportFormat::Json);
+	}
+}
\ No newline at end of file
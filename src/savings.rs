@@ -0,0 +1,82 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rust_i18n::t;
+
+/// Least reduction of the file size for a result to be kept, in bytes or in percent of the original size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinSavings {
+	Bytes(u64),
+	Percent(f64),
+}
+
+impl MinSavings {
+	pub fn is_met(&self, original_size: u64, result_size: u64) -> bool {
+		let savings = original_size as i64 - result_size as i64;
+		match *self {
+			Self::Bytes(bytes) => savings >= bytes as i64,
+			Self::Percent(percent) => savings as f64 >= original_size as f64 * percent / 100.0,
+		}
+	}
+}
+
+impl FromStr for MinSavings {
+	type Err = String;
+
+	/// Parses `BYTES` or `PERCENT%`, as in `100` or `5%`.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || t!("error-invalid-min-savings", value = s).to_string();
+		match s.strip_suffix('%') {
+			Some(percent) => match percent.trim().parse::<f64>() {
+				Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Self::Percent(percent)),
+				_ => Err(invalid()),
+			},
+			None => s.trim().parse().map(Self::Bytes).map_err(|_| invalid()),
+		}
+	}
+}
+
+impl fmt::Display for MinSavings {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Bytes(bytes) => write!(f, "{bytes}"),
+			Self::Percent(percent) => write!(f, "{percent}%"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_min_savings() {
+		assert_eq!("100".parse(), Ok(MinSavings::Bytes(100)));
+		assert_eq!("5%".parse(), Ok(MinSavings::Percent(5.0)));
+		assert!("150%".parse::<MinSavings>().is_err());
+		assert!("-1".parse::<MinSavings>().is_err());
+
+		assert!(MinSavings::Bytes(0).is_met(100, 100));
+		assert!(!MinSavings::Bytes(0).is_met(100, 101));
+		assert!(MinSavings::Bytes(10).is_met(100, 90));
+		assert!(!MinSavings::Bytes(10).is_met(100, 91));
+		assert!(MinSavings::Percent(5.0).is_met(200, 190));
+		assert!(!MinSavings::Percent(5.0).is_met(200, 191));
+	}
+}
\ No newline at end of file
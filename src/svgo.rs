@@ -14,14 +14,123 @@
 //! You should have received a copy of the GNU Affero General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::env;
+use std::ffi::OsStr;
 use std::io;
-use std::path::Path;
-use std::process;
-
-pub fn run_svgo<'a>(svg_files: impl Iterator<Item = &'a Path>, svgo_path: &Path) -> io::Result<()> {
-	let mut command = process::Command::new(svgo_path);
-	command.args(&["-q"]).args(svg_files);
-	command.status()?;
-	command.status()?; // Second time for additional optimization
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::time::Duration;
+
+use rayon::prelude::*;
+
+use crate::command::{self, args_length, batches, MAX_ARGS_LENGTH};
+
+/// Names of the SVGO configuration files, in the order SVGO itself looks for them.
+const CONFIG_FILE_NAMES: [&str; 3] = ["svgo.config.js", "svgo.config.mjs", "svgo.config.cjs"];
+
+/// A command that runs SVGO.
+pub struct SvgoCommand {
+	pub program: PathBuf,
+	/// Arguments that make the program run SVGO, as with `npx`.
+	pub args: Vec<&'static str>,
+	pub version: String,
+}
+
+impl SvgoCommand {
+	/// Uses the given program, or looks for `svgo` in `PATH`, then in `node_modules/.bin` of the
+	/// current directory or its parents, and then tries running it with `npx` and `bunx`.
+	pub fn find(program: Option<&Path>) -> Option<Self> {
+		if let Some(program) = program {
+			return Self::probe(program.to_path_buf(), Vec::new())
+		}
+		if let Some(svgo) = which::which("svgo").ok().and_then(|path| Self::probe(path, Vec::new())) {
+			return Some(svgo)
+		}
+		let current_dir = env::current_dir().ok()?;
+		let local_svgo = current_dir.ancestors()
+			.find_map(|dir| which::which_in("svgo", Some(dir.join("node_modules").join(".bin")), &current_dir).ok());
+		if let Some(svgo) = local_svgo.and_then(|path| Self::probe(path, Vec::new())) {
+			return Some(svgo)
+		}
+		// Neither runner is allowed to download SVGO
+		[("npx", vec!["--offline", "--no", "--", "svgo"]), ("bunx", vec!["--no-install", "svgo"])].into_iter()
+			.find_map(|(runner, args)| Self::probe(which::which(runner).ok()?, args))
+	}
+
+	/// Checks that the command runs SVGO by getting its version.
+	fn probe(program: PathBuf, args: Vec<&'static str>) -> Option<Self> {
+		let output = process::Command::new(&program).args(&args).arg("--version")
+			.stdin(Stdio::null())
+			.stderr(Stdio::null())
+			.output().ok()?;
+		let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+		(output.status.success() && !version.is_empty()).then_some(Self { program, args, version })
+	}
+}
+
+/// How SVGO is invoked.
+pub struct SvgoOptions {
+	pub command: SvgoCommand,
+	pub config: Option<PathBuf>,
+	/// Extra arguments passed to SVGO as they are.
+	pub args: Vec<String>,
+	/// Most times SVGO is run over a file that keeps shrinking.
+	pub max_iterations: usize,
+	/// Longest time a single run of SVGO may take.
+	pub timeout: Option<Duration>,
+}
+
+impl SvgoOptions {
+	fn args(&self) -> Vec<&OsStr> {
+		let mut args: Vec<_> = self.command.args.iter().map(OsStr::new).collect();
+		args.push(OsStr::new("-q"));
+		if let Some(config) = &self.config {
+			args.extend([OsStr::new("--config"), config.as_os_str()]);
+		}
+		args.extend(self.args.iter().map(OsStr::new));
+		args
+	}
+}
+
+/// Finds the SVGO configuration file of the project in the current directory or its parents.
+pub fn find_config() -> Option<PathBuf> {
+	let current_dir = env::current_dir().ok()?;
+	current_dir.ancestors()
+		.flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join(name)))
+		.find(|path| path.is_file())
+}
+
+/// Runs SVGO over the files again while they keep shrinking, at most `max_iterations` times.
+///
+/// Files are split into batches that fit into the command line, which run in parallel.
+/// Fails with what SVGO printed to stderr if it exits unsuccessfully.
+pub fn run_svgo(svg_files: &[&Path], svgo: &SvgoOptions) -> io::Result<()> {
+	let args = svgo.args();
+	let max_files_length = MAX_ARGS_LENGTH.saturating_sub(args_length(args.iter().copied()));
+	let mut files = svg_files.iter()
+		.map(|&path| Ok((path, path.metadata()?.len())))
+		.collect::<io::Result<Vec<_>>>()?;
+	for _ in 0..svgo.max_iterations {
+		if files.is_empty() {
+			break
+		}
+		let paths: Vec<_> = files.iter().map(|(path, _)| *path).collect();
+		batches(&paths, max_files_length).into_par_iter().try_for_each(|batch| {
+			command::run(process::Command::new(&svgo.command.program).args(&args).args(batch), "SVGO", None, svgo.timeout).map(|_| ())
+		})?;
+		files = shrunk_files(files)?;
+	}
 	Ok(())
+}
+
+/// Keeps the files that became smaller than their given sizes, with their new sizes.
+fn shrunk_files(files: Vec<(&Path, u64)>) -> io::Result<Vec<(&Path, u64)>> {
+	let mut shrunk = Vec::new();
+	for (path, size) in files {
+		let new_size = path.metadata()?.len();
+		if new_size < size {
+			shrunk.push((path, new_size));
+		}
+	}
+	Ok(shrunk)
 }
\ No newline at end of file
@@ -15,25 +15,149 @@
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
-use flate2::{Compression, write::GzEncoder};
+use brotli::enc::BrotliEncoderParams;
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
 
-pub fn compress_to_svgz(filepath: &Path) -> io::Result<PathBuf> {
+use crate::atomic_write::{write_atomically, write_atomically_with};
+use crate::files::has_svgz_extension;
 
-	let file = fs::File::open(filepath)?;
-	let reader = io::BufReader::new(file);
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
-	let svgz_filepath = filepath.with_extension("svgz");
+/// How `.svgz` files are compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SvgzCompression {
+	/// A gzip compression level from 0 to 9.
+	Level(u32),
+	/// Zopfli, producing smaller gzip files at the cost of a much longer compression.
+	Zopfli,
+}
+
+impl Default for SvgzCompression {
+	fn default() -> Self {
+		Self::Level(Compression::best().level())
+	}
+}
+
+/// Formats of the precompressed files served by static web servers next to the SVG ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrecompressedFormat {
+	Gzip,
+	Brotli,
+}
+
+impl PrecompressedFormat {
+	pub const NAMES: [&'static str; 2] = ["gz", "br"];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"gz" => Some(Self::Gzip),
+			"br" => Some(Self::Brotli),
+			_ => None,
+		}
+	}
+
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Self::Gzip => "gz",
+			Self::Brotli => "br",
+		}
+	}
+}
+
+fn write_gzip(writer: impl Write, content: &[u8], compression: SvgzCompression) -> io::Result<()> {
+	match compression {
+		SvgzCompression::Level(level) => {
+			let mut encoder = GzEncoder::new(writer, Compression::new(level));
+			encoder.write_all(content)?;
+			encoder.finish().map(|_| ())
+		}
+		SvgzCompression::Zopfli => zopfli::compress(zopfli::Options::default(), zopfli::Format::Gzip, content, writer),
+	}
+}
+
+fn write_brotli(mut writer: impl Write, content: &[u8]) -> io::Result<()> {
+	let params = BrotliEncoderParams { quality: 11, lgwin: 22, ..Default::default() };
+	brotli::BrotliCompress(&mut &content[..], &mut writer, &params).map(|_| ())
+}
+
+/// Writes precompressed copies of the file next to it, such as `icon.svg.gz` for `icon.svg`.
+/// Returns their paths. The gzip ones are compressed as `.svgz` files are.
+pub fn write_precompressed(filepath: &Path, formats: &[PrecompressedFormat], compression: SvgzCompression) -> io::Result<Vec<PathBuf>> {
+	let content = fs::read(filepath)?;
+	formats.iter().map(|format| {
+		let mut path = filepath.as_os_str().to_os_string();
+		path.push(".");
+		path.push(format.extension());
+		let path = PathBuf::from(path);
+		write_atomically_with(&path, |writer| match format {
+			PrecompressedFormat::Gzip => write_gzip(writer, &content, compression),
+			PrecompressedFormat::Brotli => write_brotli(writer, &content),
+		})?;
+		Ok(path)
+	}).collect()
+}
+
+/// Compresses the file into a sibling `.svgz` file, replacing the original one unless `keep_source` is set.
+///
+/// An `.svgz` file (holding plain SVG after decompression) is compressed in place.
+pub fn compress_to_svgz(filepath: &Path, compression: SvgzCompression, keep_source: bool) -> io::Result<PathBuf> {
 
-	let file = fs::File::create(&svgz_filepath)?;
-	let mut encoder = GzEncoder::new(file, Compression::best());
+	let content = fs::read(filepath)?;
 
-	// Copy contents from reader to encoder
-	io::copy(&mut reader.take(u64::MAX), &mut encoder)?;
+	// Keeping the own extension, as `icon.SVGZ`, since only its case may differ on some file systems
+	if has_svgz_extension(filepath) {
+		write_atomically_with(filepath, |writer| write_gzip(writer, &content, compression))?;
+		return Ok(filepath.to_path_buf())
+	}
 
-	encoder.finish()?;
-	fs::remove_file(filepath)?;
+	let svgz_filepath = filepath.with_extension("svgz");
+
+	write_atomically_with(&svgz_filepath, |writer| write_gzip(writer, &content, compression))?;
+
+	if !keep_source {
+		fs::remove_file(filepath)?;
+	}
 	Ok(svgz_filepath)
+}
+
+/// Checks the content of the file, so gzipped files are detected whatever their extension is.
+pub fn is_gzip_file(filepath: &Path) -> io::Result<bool> {
+	let mut magic = [0; 2];
+	match fs::File::open(filepath)?.read_exact(&mut magic) {
+		Ok(()) => Ok(magic == GZIP_MAGIC),
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+/// Replaces the content of a gzipped file with the decompressed one, keeping the file name.
+pub fn decompress_in_place(filepath: &Path) -> io::Result<()> {
+	let mut content = Vec::new();
+	MultiGzDecoder::new(io::BufReader::new(fs::File::open(filepath)?)).read_to_end(&mut content)?;
+	write_atomically(filepath, content)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_precompressed_round_trip() {
+		let content = b"<svg xmlns=\"http://www.w3.org/2000/svg\"><rect/><rect/><rect/></svg>";
+
+		let mut gzip = Vec::new();
+		write_gzip(&mut gzip, content, SvgzCompression::default()).unwrap();
+		let mut decompressed = Vec::new();
+		MultiGzDecoder::new(&gzip[..]).read_to_end(&mut decompressed).unwrap();
+		assert_eq!(decompressed, content);
+
+		let mut brotli = Vec::new();
+		write_brotli(&mut brotli, content).unwrap();
+		let mut decompressed = Vec::new();
+		brotli::Decompressor::new(&brotli[..], 4096).read_to_end(&mut decompressed).unwrap();
+		assert_eq!(decompressed, content);
+	}
 }
\ No newline at end of file
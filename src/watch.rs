@@ -0,0 +1,108 @@
+//! svgc is a tool for compressing SVG files
+//! Copyright (C) © 2024  Petr Alexandrovich Sabanov
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU Affero General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU Affero General Public License for more details.
+//!
+//! You should have received a copy of the GNU Affero General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use rust_i18n::t;
+
+use crate::cancel;
+use crate::files::{find_svg_files, FindOptions};
+
+/// Time without changes after which a batch of changed files is processed,
+/// so a file saved several times in a row is processed once.
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often waiting for changes is checked for being cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Watches the paths and passes the SVG files created or modified in them to `process`.
+///
+/// Changed files are filtered by the same rules as the found ones. Runs until the watcher fails,
+/// which is an error, or the processing is cancelled.
+pub fn watch(paths: &[PathBuf], find_options: &FindOptions, mut process: impl FnMut(Vec<PathBuf>)) -> notify::Result<()> {
+	let (sender, receiver) = mpsc::channel();
+	let mut watcher = notify::recommended_watcher(sender)?;
+	let mode = if find_options.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+	// Files are replaced when written, which ends watching them, so their directories are watched
+	// instead. Paths of the events in them are mapped to the given paths.
+	let mut file_paths = BTreeMap::new();
+	let mut watched_dirs = BTreeSet::new();
+	for path in paths {
+		if path.is_dir() {
+			watcher.watch(path, mode)?;
+			continue
+		}
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => Path::new("."),
+		};
+		if watched_dirs.insert(dir.to_path_buf()) {
+			watcher.watch(dir, RecursiveMode::NonRecursive)?;
+		}
+		if let Some(name) = path.file_name() {
+			file_paths.insert(dir.join(name), path.clone());
+		}
+	}
+
+	let mut changed_paths = BTreeSet::new();
+	while !cancel::is_cancelled() {
+		match receiver.recv_timeout(POLL_INTERVAL) {
+			Ok(event) => collect_changed_paths(event, &file_paths, &mut changed_paths),
+			Err(RecvTimeoutError::Timeout) => continue,
+			Err(RecvTimeoutError::Disconnected) => return Err(watcher_stopped_error()),
+		}
+		loop {
+			match receiver.recv_timeout(DEBOUNCE_INTERVAL) {
+				Ok(event) => collect_changed_paths(event, &file_paths, &mut changed_paths),
+				Err(RecvTimeoutError::Timeout) => break,
+				Err(RecvTimeoutError::Disconnected) => return Err(watcher_stopped_error()),
+			}
+		}
+		if changed_paths.is_empty() {
+			continue
+		}
+
+		match find_svg_files(paths, find_options) {
+			Ok(svg_files) => {
+				let changed_files: Vec<_> = svg_files.into_iter().filter(|path| changed_paths.contains(path)).collect();
+				if !changed_files.is_empty() {
+					process(changed_files);
+				}
+			}
+			Err(e) => eprintln!("{}", t!("error-finding-svg-files", error = e)),
+		}
+		changed_paths.clear();
+	}
+	Ok(())
+}
+
+fn watcher_stopped_error() -> notify::Error {
+	notify::Error::generic(&t!("watcher-stopped"))
+}
+
+fn collect_changed_paths(event: notify::Result<Event>, file_paths: &BTreeMap<PathBuf, PathBuf>, changed_paths: &mut BTreeSet<PathBuf>) {
+	match event {
+		Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+			changed_paths.extend(event.paths.into_iter().map(|path| file_paths.get(&path).cloned().unwrap_or(path)));
+		}
+		Ok(_) => {}
+		Err(e) => eprintln!("{}", t!("error-watching-for-changes", error = e)),
+	}
+}
\ No newline at end of file